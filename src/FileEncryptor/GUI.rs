@@ -1,21 +1,35 @@
 use iced::widget::{Column, Container, Row};
-use iced::widget::{button, button::Status, container, Button, text, Text, TextInput, checkbox, scrollable, vertical_space};
+use iced::widget::{button, button::Status, container, Button, text, Text, TextInput, checkbox, radio, scrollable};
 use iced::{Alignment, Border, Color, Element, Length, Padding, Shadow, Theme, Vector};
 use std::process;
 use std::path::PathBuf;
+use std::time::Duration;
 use rfd::FileDialog;
 
-use crate::FileEncryptor::backend::{self, Algorithm};
+use crate::FileEncryptor::backend::{self, Algorithm, Compression, Credential, CredentialKind, CryptoError, EncryptOptions, Entry, Kdf, Recipient};
 
 pub struct FileEncryptor {
     page: Page,
     passphase: Passpharse,
+    new_passphase: Passpharse,
     command: Option<Command>,
     selected_file: Option<PathBuf>,
+    mirror: Option<PathBuf>,
     method: Option<Algorithm>,
+    options: EncryptOptions,
+    kdf: Option<Kdf>,
+    unlock_time: Option<u64>,
+    keyfile: Option<PathBuf>,
+    signing_key: Option<PathBuf>,
+    unlock: CredentialKind,
+    recipient: String,
+    credentials: Vec<Credential>,
+    format: Format,
     show_pass: bool,
     message: Option<String>,
     content: String,
+    entries: Vec<Entry>,
+    archive_credential: Option<Credential>,
 }
 
 #[derive(Debug, Clone)]
@@ -25,51 +39,100 @@ pub struct Passpharse {passphase: String, confirm: String}
 pub enum Message {
     PasspharseSubmit,
     PassphaseEnter(String, String),
+    NewPassphaseEnter(String, String),
+    ChangePassphraseSubmit,
     ChangePage(Page),
     SelectFile,
+    SelectFolder,
+    SelectMirror,
+    NewArchive,
+    AddEntry,
+    ExtractEntry(String),
+    DeleteEntry(String),
     SelectMethod(Algorithm),
+    SelectUpgradeMethod(Algorithm),
+    SelectUpgradeKdf(Kdf),
+    SelectCompression(Option<Compression>),
+    KeepOwner(bool),
+    SelectPadding(Option<backend::Padding>),
+    SelectKdf(Kdf),
+    SelectUnlockTime(Option<u64>),
+    SelectKeyfile,
+    SelectPublicKey,
+    RecipientEnter(String),
+    AddCredential,
+    SelectFormat(Format),
+    GenerateKey,
+    GenerateIdentity,
+    GenerateSigningKey,
+    SelectSigningKey,
+    CheckSignature,
+    Inspect,
     ShowPass(bool),
     Back,
     Exit,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Command {Decrypt, Encrpyt, Read}
+pub enum Command {Decrypt, Encrpyt, Read, Verify, Inspect, ChangePassphrase, Upgrade, Archive, Mirror, RestoreMirror, GenerateKey, GenerateIdentity, GenerateSigningKey, CheckSignature}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {Native, Age, OpenSsl} //what file format Encrypt writes
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Page {Menu, Passpharse(Command), SelectFile(Command), SelectMethod, Process(Command), Read}
+pub enum Page {Menu, Passpharse(Command), SelectFile(Command), SelectMethod(Command), SelectKdf(Command), SelectUpgrade, ChangePassphrase, Process(Command), Read, Browse}
 
 fn menu_page() -> Container<'static, Message> { //menu page
     let column = Column::new()
         .push(btn("Encrpyt", Message::ChangePage(Page::SelectFile(Command::Encrpyt))))
         .push(btn("Decrypt", Message::ChangePage(Page::SelectFile(Command::Decrypt))))
         .push(btn("Read", Message::ChangePage(Page::SelectFile(Command::Read))))
+        .push(btn("Verify", Message::ChangePage(Page::SelectFile(Command::Verify))))
+        .push(btn("Inspect", Message::ChangePage(Page::SelectFile(Command::Inspect))))
+        .push(btn("Change Passphrase", Message::ChangePage(Page::SelectFile(Command::ChangePassphrase))))
+        .push(btn("Upgrade", Message::ChangePage(Page::SelectFile(Command::Upgrade))))
+        .push(btn("Archive", Message::ChangePage(Page::SelectFile(Command::Archive))))
+        .push(btn("Mirror Folder", Message::ChangePage(Page::SelectFile(Command::Mirror))))
+        .push(btn("Restore Mirror", Message::ChangePage(Page::SelectFile(Command::RestoreMirror))))
+        .push(btn("Generate Key", Message::GenerateKey))
+        .push(btn("Generate Identity", Message::GenerateIdentity))
+        .push(btn("Generate Signing Key", Message::GenerateSigningKey))
+        .push(btn("Check Signature", Message::ChangePage(Page::SelectFile(Command::CheckSignature))))
         .padding(Padding::from([40, 20]))
         .align_x(Alignment::Center)
         .spacing(30);
 
-    container(column)
+    container(scrollable(column).height(560)) //the menu outgrew the window
         .padding(Padding::from(20))
         .style(|_theme: &Theme| {apperance()})
 }
 
-fn select_file(command: Command, path: Option<PathBuf>) -> Container<'static, Message> { //select file page
+fn select_file(command: Command, path: Option<PathBuf>) -> Container<'static, Message> { //select file page, a whole folder can be picked for encryption too
+    let pickers = |column: Column<'static, Message>| match command {
+        Command::Encrpyt | Command::Upgrade => column.push(btn("Select", Message::SelectFile)).push(btn("Select Folder", Message::SelectFolder)),
+        Command::Archive => column.push(btn("Select", Message::SelectFile)).push(btn("New Archive", Message::NewArchive)),
+        Command::RestoreMirror => column.push(btn("Select Folder", Message::SelectFolder)),
+        _ => column.push(btn("Select", Message::SelectFile)),
+    };
     let column = if let Some(file) = &path {
-        Column::new()
-        .push(text("Please select a file:").align_x(Alignment::Start).size(20))
-        .push(btn("Select", Message::SelectFile))
+        pickers(Column::new()
+        .push(text("Please select a file:").align_x(Alignment::Start).size(20)))
         .push(text(format!("Selected File: {:?}", file)))
-        .push(btn("Submit", Message::ChangePage(match command {
-            Command::Encrpyt => Page::SelectMethod,
-            _ => Page::Passpharse(command)
-        })))
+        .push(btn("Submit", match command {
+            Command::Encrpyt => Message::ChangePage(Page::SelectMethod(Command::Encrpyt)),
+            Command::Archive if !file.exists() => Message::ChangePage(Page::SelectMethod(Command::Archive)), //a new archive, picked with New Archive
+            Command::ChangePassphrase => Message::ChangePage(Page::ChangePassphrase),
+            Command::Upgrade => Message::ChangePage(Page::SelectUpgrade),
+            Command::CheckSignature => Message::CheckSignature, //needs no key, the signature carries the public key
+            Command::Inspect => Message::Inspect, //only the header is read, so no key either
+            _ => Message::ChangePage(Page::Passpharse(command))
+        }))
         .padding(Padding::from([30, 20]))
         .align_x(Alignment::Center)
         .spacing(30)
     } else {
-        Column::new()
-        .push(text("Please select a file:").align_x(Alignment::Start).size(20))
-        .push(btn("Select", Message::SelectFile))
+        pickers(Column::new()
+        .push(text("Please select a file:").align_x(Alignment::Start).size(20)))
         .padding(Padding::from([30, 20]))
         .align_x(Alignment::Center)
         .spacing(30)
@@ -81,12 +144,58 @@ fn select_file(command: Command, path: Option<PathBuf>) -> Container<'static, Me
         .style(|_theme: &Theme| {apperance()})
 }
 
-fn select_method() -> Container<'static, Message> { //select method page
-    let column = Column::new()
-        .push(text("Please select an encryption method:").align_x(Alignment::Start).size(20))
-        .push(btn("AesGcm", Message::SelectMethod(Algorithm::AesGcm)))
-        .push(btn("AesGcmSiv", Message::SelectMethod(Algorithm::AesGcmSiv)))
-        .push(btn("ChaCha20Poly1305", Message::SelectMethod(Algorithm::ChaCha20Poly1305)))
+fn select_mirror(source: Option<PathBuf>, mirror: Option<PathBuf>) -> Container<'static, Message> { //page for picking the folder to mirror and where its encrypted copy goes
+    let mut column = Column::new()
+        .push(text("Please select the folder and its mirror:").align_x(Alignment::Start).size(20))
+        .push(btn("Select Folder", Message::SelectFolder))
+        .push(btn("Select Mirror", Message::SelectMirror));
+    if let Some(source) = &source {
+        column = column.push(text(format!("Selected Folder: {:?}", source)));
+    }
+    if let Some(mirror) = &mirror {
+        column = column.push(text(format!("Selected Mirror: {:?}", mirror)));
+    }
+    if let (Some(_), Some(mirror)) = (&source, &mirror) { //a new mirror is set up with a method and key derivation first, like an encrypted file
+        column = column.push(btn("Submit", Message::ChangePage(if backend::is_mirror(mirror) { Page::Passpharse(Command::Mirror) } else { Page::SelectMethod(Command::Mirror) })));
+    }
+    let column = column
+        .padding(Padding::from([30, 20]))
+        .align_x(Alignment::Center)
+        .spacing(30);
+
+    container(column)
+        .width(Length::Fixed(500.0))
+        .padding(Padding::from(20))
+        .style(|_theme: &Theme| {apperance()})
+}
+
+fn select_method(command: Command, options: EncryptOptions, signing_key: Option<PathBuf>) -> Container<'static, Message> { //select method page, lists every registered algorithm
+    let compression = options.compression;
+    let signing = match &signing_key { //optional, the encrypted file is signed with it afterwards
+        Some(file) => format!("Signing with {:?}", file.file_name().unwrap_or_default()),
+        None => String::from("Not signed"),
+    };
+    let mut column = Column::new().push(text("Please select an encryption method:").align_x(Alignment::Start).size(20));
+    if command == Command::Encrpyt { //new mirrors and archives only need the method, their files come later on every sync or add
+        column = column
+            .push(Row::new() //files that are already compressed are stored as they are
+                .push(radio("No compression", None, Some(compression), Message::SelectCompression))
+                .push(radio("Compress with zstd", Some(Compression::zstd()), Some(compression), Message::SelectCompression))
+                .push(radio("Compress with deflate", Some(Compression::deflate()), Some(compression), Message::SelectCompression))
+                .spacing(20))
+            .push(Row::new() //hides the exact size of the file
+                .push(radio("No padding", None, Some(options.padding), Message::SelectPadding))
+                .push(radio("Padmé padding", Some(backend::Padding::Padme), Some(options.padding), Message::SelectPadding))
+                .push(radio("Pad to a power of two", Some(backend::Padding::PowerOfTwo), Some(options.padding), Message::SelectPadding))
+                .spacing(20))
+            .push(checkbox("Keep the file's owner", options.keep_owner).on_toggle(Message::KeepOwner))
+            .push(Row::new()
+                .push(text(signing).size(16))
+                .push(small_btn("Select Signing Key", Message::SelectSigningKey))
+                .align_y(Alignment::Center)
+                .spacing(20));
+    }
+    let column = Algorithm::all().fold(column, |column, algorithm| column.push(btn(algorithm.name(), Message::SelectMethod(algorithm))))
         .padding(Padding::from([40, 20]))
         .align_x(Alignment::Center)
         .spacing(30);
@@ -96,10 +205,70 @@ fn select_method() -> Container<'static, Message> { //select method page
         .style(|_theme: &Theme| {apperance()})
 }
 
-fn passpharse_page(pass: Passpharse, is_checked: bool, warning: Option<String>) -> Container<'static, Message> { //passpharse page
+fn select_kdf(command: Command, unlock_time: Option<u64>, format: Format, folder: bool) -> Container<'static, Message> { //select key derivation page
+    let formats = if folder { //age and OpenSSL have no way to hold a directory
+        Row::new().push(text("Folders are always encrypted as a FileEncryptor archive"))
+    } else {
+        Row::new()
+            .push(radio("FileEncryptor", Format::Native, Some(format), Message::SelectFormat))
+            .push(radio("age (passphrase or public keys)", Format::Age, Some(format), Message::SelectFormat))
+            .push(radio("OpenSSL enc (one passphrase)", Format::OpenSsl, Some(format), Message::SelectFormat))
+            .spacing(20)
+    };
+    let mut column = Column::new()
+        .push(text("Please select a key derivation function:").align_x(Alignment::Start).size(20))
+        .push(Row::new()
+            .push(radio("Default cost", None, Some(unlock_time), Message::SelectUnlockTime))
+            .push(radio("Unlock in 0.5 s", Some(500), Some(unlock_time), Message::SelectUnlockTime))
+            .push(radio("Unlock in 2 s", Some(2000), Some(unlock_time), Message::SelectUnlockTime))
+            .spacing(20));
+    if command == Command::Encrpyt { //mirrors and archives only exist in our own format
+        column = column.push(formats);
+    }
+    column = column
+        .push(btn("Argon2id", Message::SelectKdf(Kdf::argon2id())))
+        .push(btn("scrypt", Message::SelectKdf(Kdf::scrypt())))
+        .push(btn("PBKDF2", Message::SelectKdf(Kdf::pbkdf2())))
+        .push(btn("Key File (no passphrase)", Message::SelectKdf(Kdf::None)));
+    if command == Command::Encrpyt { //a recipient couldn't open a mirror or archive again to change it
+        column = column.push(btn("Public Key (recipient)", Message::SelectPublicKey));
+    }
+    let column = column
+        .padding(Padding::from([40, 20]))
+        .align_x(Alignment::Center)
+        .spacing(30);
+
+    container(column)
+        .padding(Padding::from(20))
+        .style(|_theme: &Theme| {apperance()})
+}
+
+fn select_upgrade(method: Option<Algorithm>, warning: Option<String>) -> Container<'static, Message> { //page for picking what a file or every file in a folder is re-encrypted with
+    let column = Algorithm::all().fold(
+        Column::new().push(text(warning.unwrap_or_else(|| "Please select the new encryption method and key derivation:".to_string())).align_x(Alignment::Start).size(20)),
+        |column, algorithm| column.push(radio(algorithm.name(), algorithm, method, Message::SelectUpgradeMethod)),
+    )
+        .push(btn("Argon2id", Message::SelectUpgradeKdf(Kdf::argon2id())))
+        .push(btn("scrypt", Message::SelectUpgradeKdf(Kdf::scrypt())))
+        .push(btn("PBKDF2", Message::SelectUpgradeKdf(Kdf::pbkdf2())))
+        .padding(Padding::from([40, 20]))
+        .align_x(Alignment::Center)
+        .spacing(30);
+
+    container(column)
+        .padding(Padding::from(20))
+        .style(|_theme: &Theme| {apperance()})
+}
+
+fn submit_buttons(column: Column<'static, Message>, can_add: bool) -> Column<'static, Message> { //Submit, plus a button for locking the file with more than one credential
+    let column = if can_add { column.push(btn("Add Another", Message::AddCredential)) } else { column };
+    column.push(btn("Submit", Message::PasspharseSubmit))
+}
+
+fn passpharse_page(pass: Passpharse, is_checked: bool, warning: Option<String>, keyfile: Option<PathBuf>, show_keyfile: bool, can_add: bool) -> Container<'static, Message> { //passpharse page
     let pass_confirm = pass.confirm.clone();
 
-    let column = Column::new()
+    let mut column = Column::new()
         .push(text(warning.unwrap_or_else(|| "Please enter the passphrase:".to_string())).size(20))
         .push(
             input_field("Passphase...", &pass.passphase)
@@ -115,8 +284,94 @@ fn passpharse_page(pass: Passpharse, is_checked: bool, warning: Option<String>)
             })
             .secure(!is_checked)
         )
-        .push(checkbox("Show Password", is_checked).on_toggle(Message::ShowPass))
-        .push(btn("Submit", Message::PasspharseSubmit))
+        .push(checkbox("Show Password", is_checked).on_toggle(Message::ShowPass));
+
+    if show_keyfile { //optional when encrypting, only asked for on decrypt when the file was encrypted with one
+        column = column.push(btn("Select Keyfile", Message::SelectKeyfile));
+        if let Some(file) = &keyfile {
+            column = column.push(text(format!("Selected Keyfile: {:?}", file)));
+        }
+    }
+
+    let column = submit_buttons(column, can_add)
+        .padding(Padding::from([30, 20]))
+        .align_x(Alignment::Center)
+        .spacing(30);
+
+    container(column)
+        .padding(Padding::from(20))
+        .style(|_theme: &Theme| {apperance()})
+}
+
+fn change_passpharse_page(current: String, new: Passpharse, is_checked: bool, warning: Option<String>, keyfile: Option<PathBuf>, show_keyfile: bool) -> Container<'static, Message> { //page for swapping the passpharse, the file itself is not re-encrypted
+    let new_confirm = new.confirm.clone();
+
+    let mut column = Column::new()
+        .push(text(warning.unwrap_or_else(|| "Please enter the current and new passphrase:".to_string())).size(20))
+        .push(
+            input_field("Current Passphase...", &current)
+            .on_input(|passpharse| Message::PassphaseEnter(passpharse.clone(), passpharse))
+            .secure(!is_checked)
+        )
+        .push(
+            input_field("New Passphase...", &new.passphase)
+            .on_input(move |passpharse|{
+                Message::NewPassphaseEnter(passpharse, new.confirm.clone())
+            })
+            .secure(!is_checked)
+        )
+        .push(
+            input_field("Confirm New Passpharse...", &new_confirm)
+            .on_input(move |confirm|{
+                Message::NewPassphaseEnter(new.passphase.clone(), confirm)
+            })
+            .secure(!is_checked)
+        )
+        .push(checkbox("Show Password", is_checked).on_toggle(Message::ShowPass));
+
+    if show_keyfile { //the new passpharse keeps using the same keyfile
+        column = column.push(btn("Select Keyfile", Message::SelectKeyfile));
+        if let Some(file) = &keyfile {
+            column = column.push(text(format!("Selected Keyfile: {:?}", file)));
+        }
+    }
+
+    let column = column
+        .push(btn("Submit", Message::ChangePassphraseSubmit))
+        .padding(Padding::from([30, 20]))
+        .align_x(Alignment::Center)
+        .spacing(30);
+
+    container(column)
+        .padding(Padding::from(20))
+        .style(|_theme: &Theme| {apperance()})
+}
+
+fn key_page(prompt: &str, keyfile: Option<PathBuf>, warning: Option<String>, can_add: bool) -> Container<'static, Message> { //page for picking a key or identity file instead of a passpharse
+    let mut column = Column::new()
+        .push(text(warning.unwrap_or_else(|| prompt.to_string())).size(20))
+        .push(btn("Select File", Message::SelectKeyfile));
+
+    if let Some(file) = &keyfile {
+        column = column.push(text(format!("Selected File: {:?}", file)));
+    }
+
+    let column = submit_buttons(column, can_add)
+        .padding(Padding::from([30, 20]))
+        .align_x(Alignment::Center)
+        .spacing(30);
+
+    container(column)
+        .padding(Padding::from(20))
+        .style(|_theme: &Theme| {apperance()})
+}
+
+fn recipient_page(recipient: String, warning: Option<String>) -> Container<'static, Message> { //page for entering the public key to encrypt to
+    let column = Column::new()
+        .push(text(warning.unwrap_or_else(|| "Please enter the recipient's public key:".to_string())).size(20))
+        .push(input_field("age1...", &recipient).on_input(Message::RecipientEnter));
+
+    let column = submit_buttons(column, true)
         .padding(Padding::from([30, 20]))
         .align_x(Alignment::Center)
         .spacing(30);
@@ -159,6 +414,31 @@ fn read(content: String) -> Container<'static, Message> { //page for placing the
         .style(|_theme: &Theme| {apperance()})
 }
 
+fn browse(entries: &[Entry], message: Option<String>) -> Container<'static, Message> { //page listing what is in an archive, each file can be taken out or removed on its own
+    let list = entries.iter().fold(Column::new().spacing(10).width(900), |column, entry| {
+        column.push(Row::new()
+            .push(text(format!("{} ({} bytes)", entry.name, entry.size)).size(20).width(Length::Fill))
+            .push(small_btn("Extract", Message::ExtractEntry(entry.name.clone())))
+            .push(small_btn("Delete", Message::DeleteEntry(entry.name.clone())))
+            .align_y(Alignment::Center)
+            .spacing(10))
+    });
+    let column = Column::new()
+        .push(text(message.unwrap_or_else(|| if entries.is_empty() { "The archive is empty".to_string() } else { format!("{} files", entries.len()) })).size(20))
+        .push(scrollable(list).height(250))
+        .push(btn("Add File", Message::AddEntry))
+        .push(btn("Back", Message::Back))
+        .padding(Padding::from([30, 20]))
+        .align_x(Alignment::Center)
+        .spacing(20);
+
+    container(column)
+        .width(Length::Fixed(1000.0))
+        .padding(Padding::from(10))
+        .align_x(Alignment::Center)
+        .style(|_theme: &Theme| {apperance()})
+}
+
 fn apperance() -> container::Style {
     container::Style {
         text_color: Default::default(),
@@ -172,7 +452,7 @@ fn apperance() -> container::Style {
     }
 }
 
-fn btn(name: &str, event: Message) -> Button<Message> { //Creating the mainly used button
+fn btn(name: &str, event: Message) -> Button<'_, Message> { //Creating the mainly used button
     Button::new(text(name).center().size(25))
     .on_press(event)
     .width(Length::Fixed(450.0))
@@ -180,6 +460,14 @@ fn btn(name: &str, event: Message) -> Button<Message> { //Creating the mainly us
     .style(|theme: &Theme, status| {btn_style(theme, status, ButtonStyle::Standard)})
 }
 
+fn small_btn(name: &str, event: Message) -> Button<'_, Message> { //for the buttons next to each file in a list
+    Button::new(text(name).center().size(18))
+    .on_press(event)
+    .width(Length::Fixed(110.0))
+    .height(Length::Fixed(40.0))
+    .style(|theme: &Theme, status| {btn_style(theme, status, ButtonStyle::Standard)})
+}
+
 pub enum ButtonStyle{ //Types of button's style
     Standard,
     ThemeButton,
@@ -255,18 +543,52 @@ fn input_field(_placeholder: &str, _value: &str) -> TextInput<'static, Message>
         .line_height(text::LineHeight::Relative(1.75))
 }
 
-fn title(heading: &str) -> Text { //Page title custom
+fn title(heading: &str) -> Text<'_> { //Page title custom
     Text::new(heading).size(100)
 }
 
+fn preferred_kind(kinds: Vec<CredentialKind>) -> CredentialKind { //ask for a passpharse when the file takes one, then an identity, then a key
+    let keyfile = kinds.contains(&CredentialKind::Passphrase { keyfile: true });
+    if keyfile || kinds.contains(&CredentialKind::Passphrase { keyfile: false }) {
+        CredentialKind::Passphrase { keyfile }
+    } else if kinds.contains(&CredentialKind::Identity) {
+        CredentialKind::Identity
+    } else if kinds.contains(&CredentialKind::Key) {
+        CredentialKind::Key
+    } else {
+        CredentialKind::Passphrase { keyfile: false }
+    }
+}
+
+fn archive_path(dir: &std::path::Path) -> PathBuf { //where an encrypted folder goes, next to the folder
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(".fenc");
+    dir.with_file_name(name)
+}
+
+fn extracted_path(file: &std::path::Path) -> PathBuf { //where a folder is extracted before it gets its original name back
+    match file.extension() {
+        Some(_) => file.with_extension(""),
+        None => {
+            let mut name = file.file_name().unwrap_or_default().to_os_string();
+            name.push(" (extracted)");
+            file.with_file_name(name)
+        }
+    }
+}
+
 fn back_button(page: &Page) -> Button<'static, Message> { //Define which page it suppose to go back when hit the button
     Button::new(text("<-").align_x(Alignment::Start).size(50))
             .on_press(match page {
                 Page::Menu => Message::Exit,
                 Page::SelectFile(_command) => Message::ChangePage(Page::Menu),
-                Page::SelectMethod => Message::ChangePage(Page::SelectFile(Command::Encrpyt)),
+                Page::SelectMethod(command) => Message::ChangePage(Page::SelectFile(command.clone())),
+                Page::SelectKdf(command) => Message::ChangePage(Page::SelectMethod(command.clone())),
+                Page::SelectUpgrade => Message::ChangePage(Page::SelectFile(Command::Upgrade)),
+                Page::ChangePassphrase => Message::ChangePage(Page::SelectFile(Command::ChangePassphrase)),
                 Page::Passpharse(command) => match command {
-                    Command::Encrpyt => Message::ChangePage(Page::SelectMethod),
+                    Command::Encrpyt => Message::ChangePage(Page::SelectKdf(Command::Encrpyt)),
+                    Command::Upgrade => Message::ChangePage(Page::SelectUpgrade),
                     _ => Message::ChangePage(Page::SelectFile(command.clone())),
                 },
                 _ => Message::ChangePage(Page::Menu)
@@ -275,6 +597,47 @@ fn back_button(page: &Page) -> Button<'static, Message> { //Define which page it
 }
 
 impl FileEncryptor {
+    fn credential(&self) -> Result<Credential, CryptoError> { //whatever was entered on the passpharse page
+        match self.unlock {
+            CredentialKind::Key => Ok(Credential::Key(backend::read_key_file(self.keyfile.as_ref().unwrap())?)), //the picked file is the key itself
+            CredentialKind::Identity if self.command == Some(Command::Encrpyt) => Ok(Credential::Recipient(Recipient::parse(&self.recipient).unwrap())), //checked on submit
+            CredentialKind::Identity => Ok(Credential::Identity(backend::read_identity_file(self.keyfile.as_ref().unwrap())?)),
+            CredentialKind::Passphrase { .. } => Ok(Credential::Passphrase { passphrase: self.passphase.passphase.clone(), keyfile: self.keyfile.clone() }),
+        }
+    }
+
+    fn needs_file(&self) -> bool { //the key or identity has to be picked before submitting
+        match self.unlock {
+            CredentialKind::Passphrase { .. } => false, //other members of the file may not use a keyfile, the backend tells if one was needed
+            CredentialKind::Key => true,
+            CredentialKind::Identity => self.command != Some(Command::Encrpyt),
+        }
+    }
+
+    fn input_warning(&self) -> Option<String> { //what is still missing or wrong on the passpharse page
+        if self.passphase.passphase != self.passphase.confirm { //check if the passpharse and the confirm passpharse match
+            Some(String::from("Mismatch passphrases"))
+        } else if self.unlock == CredentialKind::Identity && self.command == Some(Command::Encrpyt) && Recipient::parse(&self.recipient).is_none() {
+            Some(String::from("Invalid public key"))
+        } else if self.needs_file() && self.keyfile.is_none() {
+            Some(String::from("Please select the file"))
+        } else {
+            None
+        }
+    }
+
+    fn kdf(&self) -> Result<Kdf, CryptoError> { //the picked KDF, benchmarked on this machine if the user asked for a target unlock time
+        let kdf = self.kdf.clone().unwrap_or_default();
+        match self.unlock_time {
+            Some(millis) => kdf.calibrate(Duration::from_millis(millis)),
+            None => Ok(kdf),
+        }
+    }
+
+    fn only_added(&self) -> bool { //nothing new was entered after adding credentials, so submit just those
+        !self.credentials.is_empty() && self.passphase.passphase.is_empty() && self.keyfile.is_none() && self.recipient.trim().is_empty()
+    }
+
     pub fn new() -> Self {
         Self {
             page: Page::Menu,
@@ -282,49 +645,180 @@ impl FileEncryptor {
                 passphase: String::new(),
                 confirm: String::new(),
             },
+            new_passphase: Passpharse {
+                passphase: String::new(),
+                confirm: String::new(),
+            },
             command: None,
             selected_file: None,
+            mirror: None,
             method: None,
+            options: EncryptOptions::default(),
+            kdf: None,
+            unlock_time: None,
+            keyfile: None,
+            signing_key: None,
+            unlock: CredentialKind::Passphrase { keyfile: false },
+            recipient: String::new(),
+            credentials: Vec::new(),
+            format: Format::Native,
             show_pass: false,
             message: None,
             content: String::new(),
+            entries: Vec::new(),
+            archive_credential: None,
         }
     }
 
     pub fn update(&mut self, message: Message) {
         match message {
             Message::PasspharseSubmit => { //This is when the user press the submit button on the passpharse page
-                if self.passphase.passphase != self.passphase.confirm { //check if the passpharse and the confirm passpharse match
-                    self.message = Some(String::from("Mismatch passphrases")) //if not send a message the user
+                let warning = if self.only_added() { None } else { self.input_warning() };
+                if let Some(warning) = warning {
+                    self.message = Some(warning) //if not send a message the user
                 } else {
                     self.message = None;
-                    if self.command != Some(Command::Read) { //I want the one with Encrypt and Decrypt command to go the result page
+                    if !matches!(self.command, Some(Command::Read | Command::Archive)) { //I want the one with Encrypt and Decrypt command to go the result page
                         self.page = Page::Process(self.command.clone().unwrap());
                     }
 
                     match self.command { //perform the task according to the command
                         Some(Command::Encrpyt) => {
-                            match backend::encrypt_file(self.selected_file.as_ref().unwrap(), self.selected_file.as_ref().unwrap(), &self.passphase.passphase, self.method.clone().unwrap()) {
-                                Ok(_) => self.message = Some(String::from("Encrypted File Succesfully")),
+                            let kdf = self.kdf();
+                            let mut credentials = self.credentials.clone(); //everyone added before, plus what is on the page now
+                            let credentials = if self.only_added() { Ok(credentials) } else { self.credential().map(|credential| { credentials.push(credential); credentials }) };
+                            let file = self.selected_file.as_ref().unwrap();
+                            let encrypted = kdf.and_then(|kdf| match self.format { //age and OpenSSL pick their own algorithm and KDF
+                                Format::Native if file.is_dir() => backend::encrypt_directory(file, &archive_path(file), &credentials?, self.method.unwrap(), kdf, self.options), //the folder itself is left as it is
+                                Format::Native => backend::encrypt_file(file, file, &credentials?, self.method.unwrap(), kdf, self.options),
+                                _ if file.is_dir() => Err(CryptoError::Io(std::io::Error::new(std::io::ErrorKind::Unsupported, "age and OpenSSL only encrypt single files"))),
+                                Format::Age => backend::encrypt_age_file(file, file, &credentials?, kdf),
+                                Format::OpenSsl => backend::encrypt_openssl_file(file, file, &credentials?),
+                            });
+                            let output = if file.is_dir() { archive_path(file) } else { file.clone() };
+                            let signed = encrypted.and_then(|_| match &self.signing_key { //our own files carry the signature in their header, the others get a .sig next to them
+                                Some(key) => backend::read_signing_key_file(key).and_then(|identity| backend::sign_file(&output, &identity, self.format != Format::Native)).map(|_| true),
+                                None => Ok(false),
+                            });
+                            match signed {
+                                Ok(signed) => {
+                                    let encrypted = if file.is_dir() { format!("Encrypted Folder Succesfully into {:?}", output.file_name().unwrap_or_default()) } else { String::from("Encrypted File Succesfully") };
+                                    self.message = Some(if signed { format!("{} and Signed It", encrypted) } else { encrypted });
+                                },
+                                Err(_) if file.is_dir() && self.format != Format::Native => self.message = Some(String::from("age and OpenSSL Can Only Encrypt Single Files")),
+                                Err(_) if output.exists() && self.signing_key.is_some() => self.message = Some(String::from("Encrypted, but Failed to Sign")),
                                 Err(_) => self.message = Some(String::from("Failed to Encrypt")),
                             }
                         },
                         Some(Command::Decrypt) => {
-                            match backend::create_decrypted_file(self.selected_file.as_ref().unwrap(), self.selected_file.as_ref().unwrap(), &self.passphase.passphase) {
-                                Ok(_) => self.message = Some(String::from("Decrypted File Succesfully")),
+                            let file = self.selected_file.as_ref().unwrap();
+                            let extract = backend::is_archive(file).unwrap_or(false); //folders come out next to the file, which is kept
+                            let output = if extract { extracted_path(file) } else { file.clone() };
+                            match self.credential().and_then(|credential| if extract { backend::extract_directory(file, &output, &credential) } else { backend::create_decrypted_file(file, file, &credential) }) {
+                                Ok(Some(metadata)) => match backend::restore_name(&output, &metadata) { //the file gets its original name back
+                                    Ok(restored) if restored != *file => self.message = Some(format!("Decrypted File Succesfully as {:?}", restored.file_name().unwrap_or_default())),
+                                    Ok(_) => self.message = Some(String::from("Decrypted File Succesfully")),
+                                    Err(_) => self.message = Some(String::from("Decrypted File Succesfully, but a file with its original name already exists")),
+                                },
+                                Ok(None) => self.message = Some(String::from("Decrypted File Succesfully")),
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("File Has Been Tampered With")),
+                                Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("This File Needs Its Keyfile")),
+                                Err(CryptoError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => self.message = Some(format!("{:?} Already Exists", output.file_name().unwrap_or_default())),
                                 Err(_) => self.message = Some(String::from("Incorrect Passpharse")),
                             }
                         },
+                        Some(Command::Verify) => {
+                            match self.credential().and_then(|credential| backend::verify_file(self.selected_file.as_ref().unwrap(), &credential)) {
+                                Ok(report) => self.message = Some(format!("Verified Succesfully\n{}\n{} files, {} bytes", report.format, report.files, report.bytes)),
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("File Has Been Tampered With")),
+                                Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("This File Needs Its Keyfile")),
+                                Err(CryptoError::WrongKey) => self.message = Some(String::from("Incorrect Passpharse")),
+                                Err(e) => self.message = Some(format!("Failed to Verify: {}", e)),
+                            }
+                        },
                         Some(Command::Read) => {
-                            match backend::read_file(self.selected_file.as_ref().unwrap(), &self.passphase.passphase) {
+                            match self.credential().and_then(|credential| backend::read_file(self.selected_file.as_ref().unwrap(), &credential)) {
                                 Ok(content) => {
                                     self.content = content;
                                     self.page = Page::Read;
                                 },
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("File has been tampered with")),
+                                Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("Please select the keyfile")),
                                 Err(_) => self.message = Some(String::from("Unable to decrypt or incorrect passpharse"))
                             }
                         },
-                        None => (),
+                        Some(Command::Upgrade) => {
+                            let file = self.selected_file.as_ref().unwrap();
+                            let (algorithm, kdf) = (self.method.unwrap(), self.kdf.clone().unwrap_or_default());
+                            let upgraded = self.credential().and_then(|credential| if file.is_dir() { //every file in the folder, one failing doesn't stop the rest
+                                backend::upgrade_directory(file, &credential, algorithm, kdf).map(|report| {
+                                    let failed: Vec<String> = report.failed.iter().map(|(path, _)| path.file_name().unwrap_or_default().to_string_lossy().into_owned()).collect();
+                                    let message = format!("Upgraded {} Files, {} Already Up to Date, {} Skipped", report.upgraded, report.current, report.skipped);
+                                    if failed.is_empty() { message } else { format!("{}\nFailed: {}", message, failed.join(", ")) }
+                                })
+                            } else {
+                                backend::upgrade_file(file, &credential, algorithm, kdf).map(|upgraded| String::from(if upgraded { "Upgraded File Succesfully" } else { "File Is Already Up to Date" }))
+                            });
+                            match upgraded {
+                                Ok(message) => self.message = Some(message),
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("File Has Been Tampered With")),
+                                Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("This File Needs Its Keyfile")),
+                                Err(CryptoError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidInput => self.message = Some(String::from("This File Can't Be Upgraded")),
+                                Err(_) => self.message = Some(String::from("Incorrect Passpharse")),
+                            }
+                        },
+                        Some(Command::Mirror) => {
+                            let (source, mirror) = (self.selected_file.as_ref().unwrap(), self.mirror.as_ref().unwrap());
+                            let synced = self.credential().and_then(|credential| {
+                                if !backend::is_mirror(mirror) { //the first sync sets the mirror up with what was just entered and picked
+                                    backend::create_mirror(mirror, std::slice::from_ref(&credential), self.method.unwrap(), self.kdf()?)?;
+                                }
+                                backend::sync_mirror(source, mirror, &credential, self.options)
+                            });
+                            match synced {
+                                Ok(report) if report.too_long.is_empty() => self.message = Some(format!("Mirrored Succesfully: {} encrypted, {} unchanged, {} removed", report.encrypted, report.unchanged, report.removed)),
+                                Ok(report) => {
+                                    let names: Vec<String> = report.too_long.iter().map(|path| path.display().to_string()).collect();
+                                    self.message = Some(format!("Mirrored: {} encrypted, {} unchanged, {} removed. Names Too Long to Mirror: {}", report.encrypted, report.unchanged, report.removed, names.join(", ")));
+                                },
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("Mirror Has Been Tampered With")),
+                                Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("This Mirror Needs Its Keyfile")),
+                                Err(CryptoError::WrongKey) => self.message = Some(String::from("Incorrect Passpharse")),
+                                Err(_) => self.message = Some(String::from("Failed to Mirror")),
+                            }
+                        },
+                        Some(Command::RestoreMirror) => {
+                            let mirror = self.selected_file.as_ref().unwrap();
+                            let output = extracted_path(mirror);
+                            match self.credential().and_then(|credential| backend::restore_mirror(mirror, &output, &credential)) {
+                                Ok(count) => self.message = Some(format!("Restored {} Files into {:?}", count, output.file_name().unwrap_or_default())),
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("Mirror Has Been Tampered With")),
+                                Err(CryptoError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => self.message = Some(format!("{:?} Already Exists", output.file_name().unwrap_or_default())),
+                                Err(CryptoError::Io(_)) => self.message = Some(String::from("Not a Mirrored Folder")),
+                                Err(_) => self.message = Some(String::from("Incorrect Passpharse")),
+                            }
+                        },
+                        Some(Command::Archive) => {
+                            let file = self.selected_file.as_ref().unwrap();
+                            let opened = self.credential().and_then(|credential| {
+                                if !file.exists() { //a new archive, locked with what was just entered and picked
+                                    backend::create_container(file, std::slice::from_ref(&credential), self.method.unwrap(), self.kdf()?)?;
+                                }
+                                Ok((backend::list_container(file, &credential)?, credential))
+                            });
+                            match opened {
+                                Ok((entries, credential)) => {
+                                    self.entries = entries;
+                                    self.archive_credential = Some(credential);
+                                    self.page = Page::Browse;
+                                },
+                                Err(CryptoError::Tampered) => self.message = Some(String::from("Archive has been tampered with")),
+                                Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("Please select the keyfile")),
+                                Err(CryptoError::Io(_)) => self.message = Some(String::from("This file is not an archive")),
+                                Err(_) => self.message = Some(String::from("Unable to decrypt or incorrect passpharse"))
+                            }
+                        },
+                        _ => (),
                     }
                 }
             },
@@ -332,15 +826,55 @@ impl FileEncryptor {
                 self.passphase.passphase = passpharse;
                 self.passphase.confirm = confirm;
             },
+            Message::NewPassphaseEnter(passpharse, confirm) => { //Storing the input from the new passpharse textboxes
+                self.new_passphase.passphase = passpharse;
+                self.new_passphase.confirm = confirm;
+            },
+            Message::ChangePassphraseSubmit => { //Rewriting only the key slot of the selected file
+                if self.new_passphase.passphase != self.new_passphase.confirm {
+                    self.message = Some(String::from("Mismatch passphrases"))
+                } else if self.new_passphase.passphase.is_empty() {
+                    self.message = Some(String::from("Please enter the new passphrase"))
+                } else {
+                    let current = Credential::Passphrase { passphrase: self.passphase.passphase.clone(), keyfile: self.keyfile.clone() };
+                    let new = Credential::Passphrase { passphrase: self.new_passphase.passphase.clone(), keyfile: self.keyfile.clone() };
+                    self.page = Page::Process(Command::ChangePassphrase);
+                    match backend::change_credentials(self.selected_file.as_ref().unwrap(), &current, std::slice::from_ref(&current), &[new], self.kdf.clone()) { //keeps the replaced slot's KDF unless another was chosen
+                        Ok(_) => self.message = Some(String::from("Changed Passphrase Succesfully")),
+                        Err(CryptoError::Tampered) => self.message = Some(String::from("File Has Been Tampered With")),
+                        Err(CryptoError::KeyfileRequired) => self.message = Some(String::from("This File Needs Its Keyfile")),
+                        Err(CryptoError::WrongKey) => self.message = Some(String::from("Incorrect Passpharse")),
+                        Err(_) => self.message = Some(String::from("Failed to Change Passphrase")),
+                    }
+                }
+            },
             Message::ChangePage(page) => { //Logic for page change
-                self.page = page;
+                self.page = match (page, &self.selected_file) { //an archive of several files is opened in the browser instead
+                    (Page::Passpharse(Command::Decrypt | Command::Read), Some(file)) if backend::is_container(file).unwrap_or(false) => Page::Passpharse(Command::Archive),
+                    (page, _) => page,
+                };
                 self.command = match &self.page {
                     Page::Menu => None,
                     Page::SelectFile(command) => Some(command.clone()),
                     Page::Passpharse(command) => Some(command.clone()),
                     Page::Process(command) => Some(command.clone()),
                     Page::Read => Some(Command::Read),
-                    Page::SelectMethod => Some(Command::Encrpyt),
+                    Page::Browse => Some(Command::Archive),
+                    Page::SelectMethod(command) | Page::SelectKdf(command) => Some(command.clone()),
+                    Page::SelectUpgrade => Some(Command::Upgrade),
+                    Page::ChangePassphrase => Some(Command::ChangePassphrase),
+                };
+                if let (Page::Passpharse(Command::Decrypt | Command::Read | Command::Verify | Command::Upgrade | Command::Archive) | Page::ChangePassphrase, Some(file)) = (&self.page, &self.selected_file) { //check the header so the keyfile picker only shows up when needed
+                    let file = if file.is_dir() { backend::first_upgradable(file).ok().flatten() } else { Some(file.clone()) }; //a folder takes what the files in it are locked with
+                    self.unlock = preferred_kind(file.and_then(|file| backend::accepted_credentials(&file).ok()).unwrap_or_default());
+                }
+                let mirror = match &self.page { //a mirror keeps its key slots in its config file
+                    Page::Passpharse(Command::Mirror) => self.mirror.as_ref(),
+                    Page::Passpharse(Command::RestoreMirror) => self.selected_file.as_ref(),
+                    _ => None,
+                };
+                if let Some(mirror) = mirror.filter(|mirror| backend::is_mirror(mirror)) {
+                    self.unlock = preferred_kind(backend::accepted_credentials(&backend::mirror_config(mirror)).unwrap_or_default());
                 }
             },
             Message::SelectFile => { //Selecting a file using rfd
@@ -351,34 +885,189 @@ impl FileEncryptor {
                 };
                 self.selected_file = file_path;
             },
+            Message::NewArchive => { //Picking where a new archive goes, it is created once the passpharse is entered
+                self.selected_file = FileDialog::new().set_file_name("archive.fenc").save_file().filter(|file| !file.exists());
+            },
+            Message::AddEntry => { //Encrypting another file into the open archive
+                if let (Some(input), Some(file), Some(credential)) = (FileDialog::new().pick_file(), &self.selected_file, &self.archive_credential) {
+                    self.message = Some(match backend::add_entry(file, credential, &input).and_then(|_| backend::list_container(file, credential)) {
+                        Ok(entries) => {
+                            self.entries = entries;
+                            format!("Added {:?}", input.file_name().unwrap_or_default())
+                        },
+                        Err(CryptoError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => String::from("A file with that name is already in the archive"),
+                        Err(_) => String::from("Failed to add the file"),
+                    });
+                }
+            },
+            Message::ExtractEntry(name) => { //Decrypting a single file out of the archive
+                if let (Some(output), Some(file), Some(credential)) = (FileDialog::new().set_file_name(&name).save_file(), &self.selected_file, &self.archive_credential) {
+                    self.message = Some(match backend::extract_entry(file, credential, &name, &output) {
+                        Ok(_) => format!("Extracted {}", name),
+                        Err(CryptoError::Tampered) => format!("{} has been tampered with", name),
+                        Err(_) => format!("Failed to extract {}", name),
+                    });
+                }
+            },
+            Message::DeleteEntry(name) => { //Removing a file from the archive
+                if let (Some(file), Some(credential)) = (&self.selected_file, &self.archive_credential) {
+                    self.message = Some(match backend::delete_entry(file, credential, &name).and_then(|_| backend::list_container(file, credential)) {
+                        Ok(entries) => {
+                            self.entries = entries;
+                            format!("Deleted {}", name)
+                        },
+                        Err(_) => format!("Failed to delete {}", name),
+                    });
+                }
+            },
+            Message::SelectFolder => { self.selected_file = FileDialog::new().pick_folder() }, //Selecting a whole folder to encrypt as one archive or to mirror
+            Message::SelectMirror => { self.mirror = FileDialog::new().pick_folder() }, //Selecting where the encrypted copy of a folder goes
             Message::SelectMethod(method) => { //Storing which Algorithm the user pick
                 self.method = Some(method);
+                self.page = Page::SelectKdf(self.command.clone().unwrap());
+            },
+            Message::SelectUpgradeMethod(method) => { self.method = Some(method) }, //Storing which Algorithm the files are moved to
+            Message::SelectUpgradeKdf(kdf) => { //Storing which key derivation the passpharse is moved to
+                if self.method.is_none() {
+                    self.message = Some(String::from("Please select the new encryption method"));
+                } else {
+                    self.message = None;
+                    self.kdf = Some(kdf);
+                    self.update(Message::ChangePage(Page::Passpharse(Command::Upgrade)));
+                }
+            },
+            Message::SelectCompression(compression) => { self.options.compression = compression }, //Storing whether to compress before encrypting
+            Message::SelectPadding(padding) => { self.options.padding = padding }, //Storing how to hide the file size
+            Message::KeepOwner(keep_owner) => { self.options.keep_owner = keep_owner }, //Storing whether the owner goes into the encrypted metadata
+            Message::SelectUnlockTime(unlock_time) => { self.unlock_time = unlock_time }, //Storing the target unlock time for calibration
+            Message::SelectKdf(kdf) => { //Storing which key derivation the user pick
+                self.unlock = if kdf == Kdf::None { CredentialKind::Key } else { CredentialKind::Passphrase { keyfile: false } };
+                self.kdf = Some(kdf);
+                self.page = Page::Passpharse(self.command.clone().unwrap());
+            },
+            Message::SelectFormat(format) => { self.format = format }, //Writing the age or OpenSSL format instead of our own
+            Message::SelectKeyfile => { self.keyfile = FileDialog::new().pick_file() }, //Any file can be used as a keyfile
+            Message::SelectPublicKey => { //Encrypting to someone else's public key instead of a passpharse
+                self.unlock = CredentialKind::Identity;
+                self.kdf = None;
                 self.page = Page::Passpharse(Command::Encrpyt);
             },
+            Message::RecipientEnter(recipient) => { self.recipient = recipient }, //Storing the public key from the textbox
+            Message::AddCredential => { //Keeping what was entered and clearing the page for the next person
+                if let Some(warning) = self.input_warning() {
+                    self.message = Some(warning)
+                } else {
+                    match self.credential() {
+                        Ok(credential) => {
+                            self.credentials.push(credential);
+                            self.passphase.passphase = String::new();
+                            self.passphase.confirm = String::new();
+                            self.keyfile = None;
+                            self.recipient = String::new();
+                            self.message = Some(format!("{} added, enter the next one or submit", self.credentials.len()));
+                        },
+                        Err(_) => self.message = Some(String::from("Unable to read the selected file")),
+                    }
+                }
+            },
+            Message::GenerateKey => { //Saving a fresh random key for key-only encryption
+                if let Some(path) = FileDialog::new().set_file_name("fileencryptor.key").save_file() {
+                    self.command = Some(Command::GenerateKey);
+                    self.page = Page::Process(Command::GenerateKey);
+                    match backend::generate_key_file(&path) {
+                        Ok(_) => self.message = Some(String::from("Generated Key Succesfully")),
+                        Err(_) => self.message = Some(String::from("Failed to Generate Key")),
+                    }
+                }
+            },
+            Message::GenerateIdentity => { //Saving a fresh identity and showing its public key to share
+                if let Some(path) = FileDialog::new().set_file_name("identity.txt").save_file() {
+                    self.command = Some(Command::GenerateIdentity);
+                    self.page = Page::Process(Command::GenerateIdentity);
+                    match backend::generate_identity_file(&path) {
+                        Ok(recipient) => self.message = Some(format!("Generated Identity Succesfully\nPublic Key: {}", recipient)),
+                        Err(_) => self.message = Some(String::from("Failed to Generate Identity")),
+                    }
+                }
+            },
+            Message::GenerateSigningKey => { //Saving a fresh signing key and showing its public key to share
+                if let Some(path) = FileDialog::new().set_file_name("signing.key").save_file() {
+                    self.command = Some(Command::GenerateSigningKey);
+                    self.page = Page::Process(Command::GenerateSigningKey);
+                    match backend::generate_signing_key_file(&path) {
+                        Ok(signer) => self.message = Some(format!("Generated Signing Key Succesfully\nPublic Key: {}", signer)),
+                        Err(_) => self.message = Some(String::from("Failed to Generate Signing Key")),
+                    }
+                }
+            },
+            Message::SelectSigningKey => { self.signing_key = FileDialog::new().pick_file() }, //Picking the key the encrypted file is signed with
+            Message::CheckSignature => { //Checking who signed the selected file
+                if let Some(file) = &self.selected_file {
+                    self.page = Page::Process(Command::CheckSignature);
+                    match backend::verify_signature(file) {
+                        Ok(signer) => self.message = Some(format!("Valid Signature\nSigned by: {}", signer)),
+                        Err(CryptoError::Unsigned) => self.message = Some(String::from("This File Is Not Signed")),
+                        Err(CryptoError::BadSignature) => self.message = Some(String::from("Bad Signature, the File Was Changed After Signing")),
+                        Err(_) => self.message = Some(String::from("Failed to Check the Signature")),
+                    }
+                }
+            },
+            Message::Inspect => { //Showing what the header says about the selected file
+                if let Some(file) = &self.selected_file {
+                    self.page = Page::Process(Command::Inspect);
+                    match backend::inspect_file(file) {
+                        Ok(inspection) => self.message = Some(inspection.to_string()),
+                        Err(e) => self.message = Some(format!("Unreadable Header: {}", e)),
+                    }
+                }
+            },
             Message::ShowPass(is_checked) => { self.show_pass = is_checked }, //Show or Hide passpharse
             Message::Back => { //Logic for the back button
                 self.page = Page::Menu;
                 self.passphase.passphase = String::new();
                 self.passphase.confirm = String::new();
+                self.new_passphase.passphase = String::new();
+                self.new_passphase.confirm = String::new();
                 self.command = None;
                 self.message = None;
                 self.method = None;
+                self.options = EncryptOptions::default();
+                self.kdf = None;
+                self.unlock_time = None;
+                self.keyfile = None;
+                self.signing_key = None;
+                self.unlock = CredentialKind::Passphrase { keyfile: false };
+                self.recipient = String::new();
+                self.credentials = Vec::new();
+                self.format = Format::Native;
                 self.selected_file = None;
+                self.mirror = None;
                 self.show_pass = false;
+                self.entries = Vec::new();
+                self.archive_credential = None;
             }
             Message::Exit => process::exit(0),
         }
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn view(&self) -> Element<'_, Message> {
         let content = match &self.page {
             Page::Menu => menu_page(),
+            Page::SelectFile(Command::Mirror) => select_mirror(self.selected_file.clone(), self.mirror.clone()),
             Page::SelectFile(command) => select_file(command.clone(), self.selected_file.clone()),
-            Page::SelectMethod => select_method(),
-            Page::Passpharse(_command) => passpharse_page(self.passphase.clone(), self.show_pass.clone(), self.message.clone()),
+            Page::SelectMethod(command) => select_method(command.clone(), self.options, self.signing_key.clone()),
+            Page::SelectKdf(command) => select_kdf(command.clone(), self.unlock_time, self.format, self.selected_file.as_ref().is_some_and(|file| file.is_dir())),
+            Page::SelectUpgrade => select_upgrade(self.method, self.message.clone()),
+            Page::Passpharse(command) => match self.unlock {
+                CredentialKind::Key => key_page("Please select the key file:", self.keyfile.clone(), self.message.clone(), *command == Command::Encrpyt),
+                CredentialKind::Identity if *command == Command::Encrpyt => recipient_page(self.recipient.clone(), self.message.clone()),
+                CredentialKind::Identity => key_page("Please select your identity file:", self.keyfile.clone(), self.message.clone(), false),
+                CredentialKind::Passphrase { keyfile } => passpharse_page(self.passphase.clone(), self.show_pass, self.message.clone(), self.keyfile.clone(), *command == Command::Encrpyt || *command == Command::Upgrade || keyfile, *command == Command::Encrpyt),
+            },
+            Page::ChangePassphrase => change_passpharse_page(self.passphase.passphase.clone(), self.new_passphase.clone(), self.show_pass, self.message.clone(), self.keyfile.clone(), self.unlock == CredentialKind::Passphrase { keyfile: true }),
             Page::Process(_command) => result(self.message.clone()),
             Page::Read => read(self.content.clone()),
-            _ => menu_page(),
+            Page::Browse => browse(&self.entries, self.message.clone()),
         };
 
         let back = back_button(&self.page);
@@ -393,6 +1082,17 @@ impl FileEncryptor {
                         Command::Read => title("Read File"),
                         Command::Encrpyt => title("Encrypt"),
                         Command::Decrypt => title("Decrypt"),
+                        Command::Verify => title("Verify"),
+                        Command::Inspect => title("Inspect"),
+                        Command::ChangePassphrase => title("Change Passphrase"),
+                        Command::Upgrade => title("Upgrade"),
+                        Command::Archive => title("Archive"),
+                        Command::Mirror => title("Mirror Folder"),
+                        Command::RestoreMirror => title("Restore Mirror"),
+                        Command::GenerateKey => title("Generate Key"),
+                        Command::GenerateIdentity => title("Generate Identity"),
+                        Command::GenerateSigningKey => title("Generate Signing Key"),
+                        Command::CheckSignature => title("Check Signature"),
                     }
                 } else {
                     title("FileEncryptor")
@@ -403,7 +1103,8 @@ impl FileEncryptor {
 
         let wrapper = match &self.page {
             Page::Process(_)
-            | Page::Read => { Row::new()
+            | Page::Read
+            | Page::Browse => { Row::new()
                 .width(Length::Fill)
                 .push(center)
                 .align_y(Alignment::Start)
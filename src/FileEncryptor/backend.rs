@@ -1,152 +1,1727 @@
-use aes_gcm::aead::{Aead, OsRng};
-use aes_gcm::{AeadCore, Aes256Gcm, Key, KeyInit, Nonce};
-use aes_gcm_siv::Aes256GcmSiv;
-use chacha20poly1305::{ChaCha20Poly1305, Key as ChaChaKey, Nonce as ChaChaNonce};
+use aes_gcm::aead::OsRng;
+use base64::Engine;
 use pbkdf2::pbkdf2;
 use rand::RngCore;
-use sha2::Sha256;
-use hmac::Hmac;
+use sha2::{Digest, Sha256};
+use hmac::{Hmac, Mac};
 use std::fmt;
-use std::fs::{read, write};
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
+
+mod age;
+mod archive;
+mod cipher;
+mod compression;
+mod container;
+mod header;
+mod inspect;
+mod kdf;
+mod keyslot;
+mod metadata;
+mod mirror;
+mod openssl;
+mod padding;
+mod recipient;
+mod signing;
+mod upgrade;
+mod stream;
+
+pub use cipher::{AeadSuite, Algorithm};
+pub use compression::Compression;
+pub use container::Entry;
+pub use inspect::inspect_file;
+pub use kdf::Kdf;
+pub use metadata::{restore_name, Metadata};
+pub use mirror::{create_mirror, is_mirror, mirror_config, restore_mirror, sync_mirror};
+pub use padding::Padding;
+pub use recipient::{Identity, Recipient};
+pub use signing::{Signer, SigningIdentity};
+pub use upgrade::{first_upgradable, upgrade_directory, upgrade_file};
+
+use cipher::Cipher;
+use container::Container;
+use keyslot::KeySlot;
+use padding::{PadWriter, UnpadReader};
+use signing::SignatureHasher;
+use header::{Header, EXT_COMPRESSION, EXT_KEY_CHECK, EXT_KEY_SLOT, EXT_PADDING, EXT_SIGNATURE, EXT_SLOT_MAC, FLAG_ARCHIVE, FLAG_CONTAINER, FLAG_METADATA, FORMAT_VERSION, MAGIC};
+use stream::{StreamReader, StreamWriter};
 
 const SALT_LEN: usize = 16;
 const KEY_LEN: usize = 32;
-const NONCE_LEN: usize = 12;
+const NONCE_LEN: usize = 12; //nonce size of unversioned files, versioned headers record their own
 const ITERATIONS: u32 = 100_000;
+const KEY_CHECK_LEN: usize = 16;
+const V0_ALGORITHMS: std::ops::RangeInclusive<u8> = 1..=3; //all that existed before the header, all with 12-byte nonces
 
 #[derive(Debug)]
 pub enum CryptoError {
-    AesGcm(aes_gcm::Error),
+    Aead(aes_gcm::aead::Error),
     Io(io::Error),
+    Kdf(String),
+    WrongKey,
+    Tampered,
+    KeyfileRequired,
+    KeyRequired,
+    PassphraseRequired,
+    IdentityRequired,
+    Unsigned,
+    BadSignature,
 }
 
 impl fmt::Display for CryptoError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            CryptoError::AesGcm(e) => write!(f, "AES-GCM error: {:?}", e),
+            CryptoError::Aead(e) => write!(f, "Encryption error: {:?}", e),
             CryptoError::Io(e) => write!(f, "IO error: {}", e),
+            CryptoError::Kdf(e) => write!(f, "Key derivation error: {}", e),
+            CryptoError::WrongKey => write!(f, "Incorrect passphrase or key"),
+            CryptoError::Tampered => write!(f, "The file has been tampered with or is corrupted"),
+            CryptoError::KeyfileRequired => write!(f, "This file can only be decrypted together with its keyfile"),
+            CryptoError::KeyRequired => write!(f, "This file was encrypted with a raw key, not a passphrase"),
+            CryptoError::PassphraseRequired => write!(f, "This file was encrypted with a passphrase"),
+            CryptoError::IdentityRequired => write!(f, "This file was encrypted to a public key and needs the matching identity"),
+            CryptoError::Unsigned => write!(f, "This file is not signed"),
+            CryptoError::BadSignature => write!(f, "The signature does not match the file, it was changed after signing"),
         }
     }
 }
 
 impl std::error::Error for CryptoError {}
 
-impl From<aes_gcm::Error> for CryptoError {
-    fn from(err: aes_gcm::Error) -> CryptoError {
-        CryptoError::AesGcm(err)
+impl From<aes_gcm::aead::Error> for CryptoError {
+    fn from(err: aes_gcm::aead::Error) -> CryptoError {
+        CryptoError::Aead(err)
     }
 }
 
 impl From<io::Error> for CryptoError {
     fn from(err: io::Error) -> CryptoError {
+        if err.get_ref().is_some_and(|inner| inner.is::<CryptoError>()) { //unwrap errors that went through a Read/Write impl
+            return *err.into_inner().unwrap().downcast::<CryptoError>().unwrap();
+        }
         CryptoError::Io(err)
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Algorithm {
-    AesGcm,
-    AesGcmSiv,
-    ChaCha20Poly1305,
+impl From<CryptoError> for io::Error {
+    fn from(err: CryptoError) -> io::Error {
+        match err {
+            CryptoError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+fn private_options() -> fs::OpenOptions { //only the owner may read what is written, keys and plaintext alike
+    let mut options = fs::OpenOptions::new();
+    options.write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+fn create_private_file(path: &Path) -> io::Result<File> {
+    private_options().create(true).truncate(true).open(path)
+}
+
+fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+// the hidden path next to the output that a file or directory is built under before it is renamed into place,
+// whatever an interrupted run left there is removed first (a symlink itself, never what it points to)
+fn temp_path(output_path: &Path) -> Result<PathBuf, CryptoError> {
+    let file_name = output_path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Output path has no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".fenc-tmp");
+    let temp_path = output_path.with_file_name(temp_name);
+    match fs::symlink_metadata(&temp_path) {
+        Ok(leftover) if leftover.is_dir() => fs::remove_dir_all(&temp_path)?,
+        Ok(_) => fs::remove_file(&temp_path)?,
+        Err(_) => (),
+    }
+    Ok(temp_path)
+}
+
+pub fn derive_key_from_password(password: &str, salt: &[u8]) -> [u8; KEY_LEN] { //the fixed PBKDF2 derivation used by unversioned files
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, ITERATIONS, &mut key);
+    key
+}
+
+#[derive(Clone)]
+pub enum Credential { //what a file is locked with
+    Passphrase { passphrase: String, keyfile: Option<PathBuf> },
+    Key([u8; KEY_LEN]), //a raw 256-bit key, used as is without a KDF
+    Recipient(Recipient), //encrypt to someone else's public key
+    Identity(Identity), //the private key that opens files encrypted to its public key
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialKind { //what the UI has to ask for to decrypt a file
+    Passphrase { keyfile: bool },
+    Key,
+    Identity,
 }
 
-impl Algorithm { //set id for each Algorithm
-    fn identifier(&self) -> u8 {
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { //never print a passphrase or key
         match self {
-            Algorithm::AesGcm => 1,
-            Algorithm::AesGcmSiv => 2,
-            Algorithm::ChaCha20Poly1305 => 3,
+            Credential::Passphrase { keyfile, .. } => f.debug_struct("Passphrase").field("passphrase", &"<redacted>").field("keyfile", keyfile).finish(),
+            Credential::Key(_) => f.write_str("Key(<redacted>)"),
+            Credential::Recipient(recipient) => f.debug_tuple("Recipient").field(recipient).finish(),
+            Credential::Identity(identity) => f.debug_tuple("Identity").field(identity).finish(),
         }
     }
+}
 
-    fn from_identifier(id: u8) -> Option<Self> {
-        match id {
-            1 => Some(Algorithm::AesGcm),
-            2 => Some(Algorithm::AesGcmSiv),
-            3 => Some(Algorithm::ChaCha20Poly1305),
-            _ => None,
+impl Credential {
+    fn secret(&self, with_keyfile: bool) -> Result<Vec<u8>, CryptoError> { //the KDF input, with the keyfile's hash mixed in when the file uses one
+        match self {
+            Credential::Key(key) => Ok(key.to_vec()),
+            Credential::Recipient(_) | Credential::Identity(_) => Err(CryptoError::IdentityRequired),
+            Credential::Passphrase { passphrase, keyfile } => {
+                let mut secret = passphrase.as_bytes().to_vec();
+                match (with_keyfile, keyfile) {
+                    (true, None) => return Err(CryptoError::KeyfileRequired),
+                    (true, Some(keyfile)) => {
+                        let mut hasher = Sha256::new();
+                        io::copy(&mut File::open(keyfile)?, &mut hasher)?;
+                        secret.extend_from_slice(&hasher.finalize());
+                    }
+                    (false, _) => (), //a keyfile given for a file that was encrypted without one is ignored
+                }
+                Ok(secret)
+            }
         }
     }
 }
 
-pub fn derive_key_from_password(password: &str, salt: &[u8]) -> [u8; KEY_LEN] {
+pub fn generate_key_file(path: &Path) -> Result<(), CryptoError> { //a fresh random key, saved as hex
     let mut key = [0u8; KEY_LEN];
-    pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, ITERATIONS, &mut key);
-    key
+    OsRng.fill_bytes(&mut key);
+    writeln!(create_private_file(path)?, "{}", hex::encode(key))?;
+    Ok(())
 }
 
-pub fn encrypt_file(input_path: &PathBuf, output_path: &PathBuf, password: &str, algorithm: Algorithm) -> Result<(), CryptoError> {
-    let mut salt = [0u8; SALT_LEN];
-    OsRng.fill_bytes(&mut salt);
-    let key = derive_key_from_password(password, &salt);
-    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+// identities and signing keys are saved the same way: a comment with the public key, then the bech32 secret
+fn write_secret_key_file(path: &Path, public: &dyn fmt::Display, secret: &dyn fmt::Display) -> Result<(), CryptoError> {
+    let mut file = create_private_file(path)?;
+    writeln!(file, "# public key: {}", public)?;
+    writeln!(file, "{}", secret)?;
+    Ok(())
+}
 
-    let plaintext = read(input_path)?;
+fn read_secret_key_file<T>(path: &Path, parse: fn(&str) -> Option<T>, missing: &str) -> Result<T, CryptoError> { //the first line that parses, comments are skipped
+    fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .find_map(parse)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, missing.to_string()).into())
+}
+
+pub fn generate_identity_file(path: &Path) -> Result<Recipient, CryptoError> { //a fresh X25519 identity, returns the public key to share
+    let identity = Identity::generate();
+    let recipient = identity.to_recipient();
+    write_secret_key_file(path, &recipient, &identity)?;
+    Ok(recipient)
+}
 
-    let ciphertext = match algorithm {
-        Algorithm::AesGcm => {
-            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
-            cipher.encrypt(&nonce, plaintext.as_ref())?
+pub fn read_identity_file(path: &Path) -> Result<Identity, CryptoError> {
+    read_secret_key_file(path, Identity::parse, "No identity found in file")
+}
+
+pub fn generate_signing_key_file(path: &Path) -> Result<Signer, CryptoError> { //a fresh Ed25519 signing key, returns the public key others verify against
+    let identity = SigningIdentity::generate();
+    let signer = identity.to_signer();
+    write_secret_key_file(path, &signer, &identity)?;
+    Ok(signer)
+}
+
+pub fn read_signing_key_file(path: &Path) -> Result<SigningIdentity, CryptoError> {
+    read_secret_key_file(path, SigningIdentity::parse, "No signing key found in file")
+}
+
+pub fn read_key_file(path: &Path) -> Result<[u8; KEY_LEN], CryptoError> { //accepts the hex written by generate_key_file or base64
+    let text = fs::read_to_string(path)?;
+    let text = text.trim();
+    let bytes = if text.len() == KEY_LEN * 2 {
+        hex::decode(text).ok()
+    } else {
+        base64::engine::general_purpose::STANDARD.decode(text).ok()
+    };
+    bytes.and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Key file must contain a 256-bit key in hex or base64").into())
+}
+
+fn key_check(key: &[u8]) -> [u8; KEY_CHECK_LEN] { //lets a wrong key be told apart from a tampered file
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"FileEncryptor key check");
+    let mut check = [0u8; KEY_CHECK_LEN];
+    check.copy_from_slice(&mac.finalize().into_bytes()[..KEY_CHECK_LEN]);
+    check
+}
+
+fn slot_mac(key: &[u8], header: &Header) -> Hmac<Sha256> { //binds the key slots to the file key, so they can't be swapped or dropped unnoticed
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"FileEncryptor key slots");
+    for slot in header.extensions_of(EXT_KEY_SLOT) {
+        mac.update(&(slot.len() as u32).to_le_bytes());
+        mac.update(slot);
+    }
+    mac
+}
+
+fn slots_authentic(key: &[u8], header: &Header) -> bool {
+    header.extension(EXT_SLOT_MAC).is_some_and(|tag| slot_mac(key, header).verify_slice(tag).is_ok())
+}
+
+fn write_atomically<F>(output_path: &Path, write: F) -> Result<(), CryptoError>
+where
+    F: FnOnce(&mut File) -> Result<(), CryptoError>,
+{ //write into a temporary file next to the output and only replace the output once everything succeeded
+    write_atomically_with_mode(output_path, |file| write(file).map(|_| false))
+}
+
+fn write_atomically_with_mode<F>(output_path: &Path, write: F) -> Result<(), CryptoError>
+where
+    F: FnOnce(&mut File) -> Result<bool, CryptoError>,
+{ //like write_atomically, but write returns whether it already gave the file its own permissions
+    let temp_path = temp_path(output_path)?;
+    let result = private_options().create_new(true).open(&temp_path).map_err(CryptoError::from).and_then(|mut file| {
+        let mode_set = write(&mut file)?;
+        if let (false, Ok(existing)) = (mode_set, fs::metadata(output_path)) { //replacing a file keeps its permissions, new files stay private
+            file.set_permissions(existing.permissions())?;
         }
-        Algorithm::AesGcmSiv => {
-            let cipher = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&key));
-            cipher.encrypt(&nonce, plaintext.as_ref())?
+        file.sync_all()?;
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => Ok(fs::rename(&temp_path, output_path)?),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
         }
-        Algorithm::ChaCha20Poly1305 => {
-            let cipher = ChaCha20Poly1305::new(ChaChaKey::from_slice(&key));
-            let nonce = ChaChaNonce::from_slice(nonce.as_slice());
-            cipher.encrypt(nonce, plaintext.as_ref())?
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncryptOptions { //the optional extras of encrypt_file
+    pub compression: Option<Compression>,
+    pub keep_owner: bool, //store the unix owner in the encrypted metadata as well
+    pub padding: Option<Padding>, //hide the exact size of the file
+}
+
+pub fn encrypt_file(input_path: &Path, output_path: &Path, credentials: &[Credential], algorithm: Algorithm, kdf: Kdf, options: EncryptOptions) -> Result<(), CryptoError> {
+    let metadata = Metadata::from_file(input_path, options.keep_owner)?;
+    let mut input = File::open(input_path)?;
+    let mut prefix = Vec::new();
+    (&mut input).take(16).read_to_end(&mut prefix)?;
+    let compression = options.compression.filter(|_| !compression::looks_compressed(&prefix)); //already compressed data wouldn't shrink
+    let mut input = io::Cursor::new(prefix).chain(input);
+
+    let options = EncryptOptions { compression, ..options };
+    let (header, key) = new_header(credentials, algorithm, kdf, options, FLAG_METADATA)?;
+    seal(output_path, &header, &key, options, &metadata, |writer| {
+        let copied = io::copy(&mut input, writer)?;
+        if metadata.size.is_some_and(|size| size != copied) { //the sealed size would not match and decrypting it would fail
+            return Err(io::Error::other("The file changed while it was being encrypted"));
         }
+        Ok(())
+    })
+}
+
+pub fn encrypt_directory(input_dir: &Path, output_path: &Path, credentials: &[Credential], algorithm: Algorithm, kdf: Kdf, options: EncryptOptions) -> Result<(), CryptoError> { //the whole tree in one file, see archive.rs
+    if !input_dir.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only a directory can be encrypted as an archive").into());
+    }
+    let metadata = Metadata { size: None, ..Metadata::from_file(input_dir, options.keep_owner)? }; //the name to extract it under
+
+    let (header, key) = new_header(credentials, algorithm, kdf, options, FLAG_METADATA | FLAG_ARCHIVE)?;
+    seal(output_path, &header, &key, options, &metadata, |writer| {
+        archive::pack(input_dir, writer)?;
+        Ok(())
+    })
+}
+
+fn new_header(credentials: &[Credential], algorithm: Algorithm, kdf: Kdf, options: EncryptOptions, flags: u32) -> Result<(Header, [u8; KEY_LEN]), CryptoError> { //also returns the file key the slots wrap
+    if credentials.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "At least one passphrase, key or recipient is needed").into());
+    }
+    //a random file key, with one wrapped copy per credential so any of them can unlock the file
+    let mut key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    let slots = credentials.iter().map(|credential| Ok(KeySlot::seal(credential, &kdf, &key)?.to_bytes())).collect::<Result<_, CryptoError>>()?;
+    Ok((header_with_slots(algorithm, slots, &key, options, flags), key))
+}
+
+fn header_with_slots(algorithm: Algorithm, slots: Vec<Vec<u8>>, key: &[u8; KEY_LEN], options: EncryptOptions, flags: u32) -> Header { //a fresh nonce, the slots and everything that goes with them
+    let mut nonce_prefix = vec![0u8; algorithm.nonce_prefix_len()];
+    OsRng.fill_bytes(&mut nonce_prefix);
+    let mut header = Header::new(algorithm, &nonce_prefix);
+    for slot in slots {
+        header.add_extension(EXT_KEY_SLOT, slot);
+    }
+    header.set_extension(EXT_KEY_CHECK, key_check(key).to_vec());
+    header.flags |= flags;
+    if let Some(compression) = options.compression {
+        header.set_extension(EXT_COMPRESSION, compression.to_bytes());
+    }
+    if let Some(padding) = options.padding {
+        header.set_extension(EXT_PADDING, vec![padding.identifier()]);
+    }
+    header.set_extension(EXT_SLOT_MAC, slot_mac(key, &header).finalize().into_bytes().to_vec());
+    header
+}
+
+fn seal<F>(output_path: &Path, header: &Header, key: &[u8], options: EncryptOptions, metadata: &Metadata, content: F) -> Result<(), CryptoError>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{ //writes a versioned file around whatever content writes into the payload
+    write_atomically(output_path, |output| {
+        output.write_all(&header.to_bytes()?)?;
+
+        //the header is authenticated with every segment, so changing any of it is detected
+        let writer = StreamWriter::new(output, Cipher::new(&header.algorithm, key), &header.nonce, &header.associated_data()?);
+        let mut writer = PadWriter::new(writer);
+        metadata.write_to(&mut writer)?; //name, size, times and mode are sealed too, never in the clear
+        match options.compression {
+            Some(compression) => writer = compression.compress(writer, content)?,
+            None => content(&mut writer)?,
+        }
+        let writer = match options.padding {
+            Some(padding) => writer.finish(padding)?,
+            None => writer.into_inner(),
+        };
+        writer.finish()?;
+        Ok(())
+    })
+}
+
+pub fn encrypt_age_file(input_path: &Path, output_path: &Path, credentials: &[Credential], kdf: Kdf) -> Result<(), CryptoError> { //an age v1 file, for people who use age instead
+    if credentials.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "At least one passphrase or recipient is needed").into());
+    }
+    let mut input = File::open(input_path)?;
+
+    write_atomically(output_path, |output| age::encrypt(&mut input, output, credentials, &kdf))
+}
+
+pub fn encrypt_openssl_file(input_path: &Path, output_path: &Path, credentials: &[Credential]) -> Result<(), CryptoError> { //what `openssl enc -d -aes-256-cbc -pbkdf2` can read, for old scripts
+    let passphrase = match credentials {
+        [Credential::Passphrase { passphrase, keyfile: None }] => passphrase,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "OpenSSL files can only be locked with a single passphrase").into()),
     };
+    let mut input = File::open(input_path)?;
 
-    let mut output = Vec::new();
-    output.push(algorithm.identifier());
-    output.extend_from_slice(&salt);
-    output.extend_from_slice(nonce.as_slice());
-    output.extend_from_slice(&ciphertext);
+    write_atomically(output_path, |output| openssl::encrypt(&mut input, output, passphrase))
+}
 
-    write(output_path, output)?;
-    Ok(())
+enum Encrypted<R: Read> { //an encrypted file with its layout detected and its header parsed
+    Unversioned(io::Chain<io::Cursor<[u8; MAGIC.len()]>, R>),
+    Versioned(Header, R),
+    Age(age::Header, io::BufReader<io::Chain<io::Cursor<[u8; MAGIC.len()]>, R>>),
+    OpenSsl(R),
 }
 
-pub fn decrypted_file(input_path: &PathBuf, password: &str) -> Result<Vec<u8>, CryptoError> {
-    let data = read(input_path)?;
-    let (id, rest) = data.split_at(1);
-    let algorithm = Algorithm::from_identifier(id[0]).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown algorithm identifier"))?;
-    let (salt, rest) = rest.split_at(SALT_LEN);
-    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+fn truncated(e: CryptoError) -> CryptoError { //the magic was there, so running out of bytes means a damaged header rather than some other file
+    match e {
+        CryptoError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::InvalidData, "The header is truncated").into(),
+        e => e,
+    }
+}
 
-    let key = derive_key_from_password(password, salt);
-    let nonce = Nonce::from_slice(nonce);
+fn open_encrypted<R: Read>(mut input: R) -> Result<Encrypted<R>, CryptoError> {
+    let mut magic = [0u8; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if age::VERSION_LINE.as_bytes().starts_with(&magic) { //files written by age or for age users
+        let mut input = io::BufReader::new(io::Cursor::new(magic).chain(input));
+        return Ok(Encrypted::Age(age::Header::read_from(&mut input).map_err(truncated)?, input));
+    }
+    if magic == openssl::MAGIC { //written by `openssl enc -pbkdf2`
+        return Ok(Encrypted::OpenSsl(input));
+    }
+    if magic != MAGIC { //no magic means the unversioned layout from before the header existed
+        return Ok(Encrypted::Unversioned(io::Cursor::new(magic).chain(input)));
+    }
+
+    let mut version = [0u8; 1];
+    input.read_exact(&mut version).map_err(|e| truncated(e.into()))?;
+    match version[0] {
+        FORMAT_VERSION => Ok(Encrypted::Versioned(Header::read_from(&mut input, FORMAT_VERSION).map_err(truncated)?, input)),
+        v => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unsupported format version {} (only {} is supported)", v, FORMAT_VERSION)).into()),
+    }
+}
+
+fn key_slots(header: &Header) -> Vec<KeySlot> {
+    header.extensions_of(EXT_KEY_SLOT).filter_map(KeySlot::parse).collect()
+}
 
-    match algorithm {
-        Algorithm::AesGcm => {
-            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
-            Ok(cipher.decrypt(nonce, ciphertext.as_ref())?)
+fn credential_kinds(header: &Header) -> Vec<CredentialKind> {
+    let mut kinds: Vec<CredentialKind> = Vec::new();
+    for kind in key_slots(header).iter().map(KeySlot::kind) {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
         }
-        Algorithm::AesGcmSiv => {
-            let cipher = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(&key));
-            Ok(cipher.decrypt(nonce, ciphertext.as_ref())?)
+    }
+    kinds
+}
+
+fn passphrase_kdfs(header: &Header) -> Vec<Kdf> { //what the file's passphrases are stretched with, one per passphrase slot
+    key_slots(header).into_iter().filter_map(|slot| match slot {
+        KeySlot::Passphrase { kdf, .. } => Some(kdf),
+        _ => None,
+    }).collect()
+}
+
+pub fn accepted_credentials(input_path: &Path) -> Result<Vec<CredentialKind>, CryptoError> { //lets the UI ask for the right thing before trying to decrypt
+    match open_encrypted(File::open(input_path)?)? {
+        Encrypted::Versioned(header, _) => Ok(credential_kinds(&header)),
+        Encrypted::Unversioned(_) | Encrypted::OpenSsl(_) => Ok(vec![CredentialKind::Passphrase { keyfile: false }]),
+        Encrypted::Age(header, _) => Ok(header.credential_kinds()),
+    }
+}
+
+fn decrypt<W: Write>(input_path: &Path, credential: &Credential, output: &mut W) -> Result<Option<Metadata>, CryptoError> { //also returns the sealed metadata, if the file has any
+    match (open_encrypted(File::open(input_path)?)?, credential) {
+        (Encrypted::Versioned(header, _), _) if header.flags & header::FLAG_MIRROR != 0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "This file only holds the keys of a mirrored folder").into()),
+        (Encrypted::Versioned(header, _), _) if header.flags & FLAG_CONTAINER != 0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "This file is an archive of several files, open it to pick one").into()),
+        (Encrypted::Versioned(header, _), _) if header.flags & FLAG_ARCHIVE != 0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "This file holds a directory, extract it instead").into()),
+        (Encrypted::Versioned(header, input), credential) => decrypt_versioned(header, input, credential, |reader, _| io::copy(reader, output).map(|_| ())),
+        (Encrypted::Unversioned(input), Credential::Passphrase { passphrase, .. }) => decrypt_v0(input, passphrase, output).map(|_| None),
+        (Encrypted::Unversioned(_), _) => Err(CryptoError::PassphraseRequired),
+        (Encrypted::Age(header, input), credential) => age::decrypt(&header, input, credential, output).map(|_| None),
+        (Encrypted::OpenSsl(input), Credential::Passphrase { passphrase, .. }) => openssl::decrypt(input, passphrase, output).map(|_| None),
+        (Encrypted::OpenSsl(_), _) => Err(CryptoError::PassphraseRequired),
+    }
+}
+
+fn unlock(header: &Header, credential: &Credential) -> Result<[u8; KEY_LEN], CryptoError> { //the file key, checked against the key check and slot MAC
+    if header.nonce.len() != header.algorithm.nonce_prefix_len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid nonce length").into());
+    }
+    let key = keyslot::open_any(&key_slots(header), credential)?;
+    if header.extension(EXT_KEY_CHECK).is_some_and(|check| check != key_check(&key)) {
+        return Err(CryptoError::WrongKey);
+    }
+    if !slots_authentic(&key, header) {
+        return Err(CryptoError::Tampered);
+    }
+    Ok(key)
+}
+
+fn decrypt_versioned<R: Read, F>(header: Header, input: R, credential: &Credential, consume: F) -> Result<Option<Metadata>, CryptoError>
+where
+    F: FnOnce(&mut dyn Read, Option<&Metadata>) -> io::Result<()>,
+{ //consume gets the plaintext that follows the metadata
+    let key = unlock(&header, credential)?;
+    decrypt_with_key(&header, &key, input, consume)
+}
+
+fn decrypt_with_key<R: Read, F>(header: &Header, key: &[u8], input: R, consume: F) -> Result<Option<Metadata>, CryptoError>
+where
+    F: FnOnce(&mut dyn Read, Option<&Metadata>) -> io::Result<()>,
+{
+    let aad = header.associated_data()?;
+    let check = header.extension(EXT_KEY_CHECK);
+
+    let compression = header.extension(EXT_COMPRESSION).map(Compression::from_bytes).transpose()?;
+    let padding = header.extension(EXT_PADDING).map(Padding::from_bytes).transpose()?;
+    let mut reader = StreamReader::new(input, Cipher::new(&header.algorithm, key), &header.nonce, &aad);
+    let result = (|| -> io::Result<Option<Metadata>> {
+        let metadata = if header.flags & FLAG_METADATA != 0 { Some(Metadata::read_from(&mut reader)?) } else { None };
+        let reader: Box<dyn Read> = match padding {
+            Some(_) => Box::new(UnpadReader::new(reader)),
+            None => Box::new(reader),
+        };
+        let mut reader = match compression {
+            Some(compression) => compression.decompressor(reader)?,
+            None => reader,
+        };
+        consume(&mut reader, metadata.as_ref())?;
+        Ok(metadata)
+    })();
+    match result {
+        Ok(metadata) => Ok(metadata),
+        Err(e) => match CryptoError::from(e) { //the key is known to be right, so a failing tag means the file was modified
+            CryptoError::Aead(_) if check.is_some() => Err(CryptoError::Tampered),
+            CryptoError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof && check.is_some() => Err(CryptoError::Tampered),
+            e => Err(e),
+        },
+    }
+}
+
+// rotate a passphrase or add and remove credentials by rewriting only the key slots, the payload is copied still sealed.
+// The copy goes through a temporary file like every other write, so even adding one recipient to a large file takes as
+// long as copying it, but a crash never leaves a half-written header behind.
+// Without a kdf the new slots keep the one of the passphrase slot being replaced
+pub fn change_credentials(path: &Path, unlock: &Credential, remove: &[Credential], add: &[Credential], kdf: Option<Kdf>) -> Result<(), CryptoError> {
+    let mut input = File::open(path)?;
+    let header = match open_encrypted(&mut input)? {
+        Encrypted::Versioned(header, _) => header,
+        Encrypted::Age(..) | Encrypted::OpenSsl(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "age and OpenSSL files can't be changed without re-encrypting them").into()),
+        Encrypted::Unversioned(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "This file is from before key slots, upgrade it before changing them").into()),
+    };
+    let key = keyslot::open_any(&key_slots(&header), unlock)?;
+    if header.extension(EXT_KEY_CHECK).is_some_and(|check| check != key_check(&key)) || !slots_authentic(&key, &header) {
+        return Err(CryptoError::Tampered);
+    }
+
+    let mut matched = vec![false; remove.len()];
+    let mut slots = Vec::new();
+    let mut replaced_kdf = None;
+    for raw in header.extensions_of(EXT_KEY_SLOT) {
+        let mut removed = false;
+        if let Some(slot) = KeySlot::parse(raw) { //slots this version doesn't understand are kept as they are
+            for (credential, matched) in remove.iter().zip(matched.iter_mut()) {
+                if slot.matches(credential)? {
+                    *matched = true;
+                    removed = true;
+                }
+            }
+            if let (true, KeySlot::Passphrase { kdf, .. }) = (removed, &slot) {
+                replaced_kdf.get_or_insert_with(|| kdf.clone());
+            }
         }
-        Algorithm::ChaCha20Poly1305 => {
-            let cipher = ChaCha20Poly1305::new(ChaChaKey::from_slice(&key));
-            let nonce = ChaChaNonce::from_slice(nonce.as_slice());
-            Ok(cipher.decrypt(nonce, ciphertext.as_ref())?)
+        if !removed {
+            slots.push(raw.to_vec());
         }
     }
+    if matched.contains(&false) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A credential to remove does not unlock this file").into());
+    }
+    let kdf = kdf.or(replaced_kdf).unwrap_or_default();
+    for credential in add {
+        slots.push(KeySlot::seal(credential, &kdf, &key)?.to_bytes());
+    }
+    if slots.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A file needs at least one passphrase, key or recipient").into());
+    }
+
+    let mut new_header = header.clone();
+    new_header.extensions.retain(|e| e.kind != EXT_KEY_SLOT);
+    for slot in slots {
+        new_header.add_extension(EXT_KEY_SLOT, slot);
+    }
+    new_header.set_extension(EXT_SLOT_MAC, slot_mac(&key, &new_header).finalize().into_bytes().to_vec());
+    let header_bytes = new_header.to_bytes()?;
+
+    write_atomically(path, |output| {
+        output.write_all(&header_bytes)?;
+        io::copy(&mut input, output)?;
+        Ok(())
+    })
 }
 
-pub fn create_decrypted_file(input_path: &PathBuf, output_path: &PathBuf, password: &str) -> Result<(), CryptoError> {
-    let plaintext = decrypted_file(input_path, password)?;
+fn v0_algorithm(id: u8) -> Option<Algorithm> { //the first byte of an unversioned file, anything else means it isn't one
+    V0_ALGORITHMS.contains(&id).then(|| Algorithm::from_identifier(id)).flatten()
+}
 
-    write(output_path, plaintext)?;
+fn decrypt_v0<R: Read, W: Write>(mut input: R, password: &str, output: &mut W) -> Result<(), CryptoError> { //[algorithm id][salt][nonce][ciphertext]
+    let mut id = [0u8; 1];
+    input.read_exact(&mut id)?;
+    let algorithm = v0_algorithm(id[0]).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Not an encrypted file or unknown algorithm identifier"))?;
+    let mut salt = [0u8; SALT_LEN];
+    input.read_exact(&mut salt)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    input.read_exact(&mut nonce)?;
+    let key = derive_key_from_password(password, &salt);
+
+    let mut ciphertext = Vec::new(); //sealed as a single message
+    input.read_to_end(&mut ciphertext)?;
+    output.write_all(&Cipher::new(&algorithm, &key).decrypt(&nonce, &[], &ciphertext)?)?;
     Ok(())
 }
 
-pub fn read_file(input_path: &PathBuf, password: &str) -> Result<String, CryptoError> {
-    let plaintext = String::from_utf8(decrypted_file(input_path, password)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+pub fn decrypted_file(input_path: &Path, credential: &Credential) -> Result<Vec<u8>, CryptoError> {
+    let mut plaintext = Vec::new();
+    let metadata = decrypt(input_path, credential, &mut plaintext)?;
+    check_size(metadata.as_ref(), plaintext.len() as u64)?;
+    Ok(plaintext)
+}
+
+fn check_size(metadata: Option<&Metadata>, len: u64) -> Result<(), CryptoError> { //the size sealed with the metadata has to match what was decrypted
+    match metadata.and_then(|metadata| metadata.size) {
+        Some(size) if size != len => Err(CryptoError::Tampered),
+        _ => Ok(()),
+    }
+}
+
+pub fn create_decrypted_file(input_path: &Path, output_path: &Path, credential: &Credential) -> Result<Option<Metadata>, CryptoError> { //restores the times, mode and owner too
+    let mut metadata = None;
+    write_atomically_with_mode(output_path, |output| {
+        metadata = decrypt(input_path, credential, output)?;
+        check_size(metadata.as_ref(), output.metadata()?.len())?;
+        if let Some(metadata) = &metadata {
+            metadata.apply(output)?;
+        }
+        Ok(cfg!(unix) && metadata.as_ref().is_some_and(|metadata| metadata.mode.is_some())) //the sealed mode wins over the replaced file's
+    })?;
+    Ok(metadata)
+}
+
+// what verify_file found: the file decrypted all the way through and every tag checked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub format: String,
+    pub files: usize, //1 for a single file, the entries of an archive, 0 for a mirror's key file
+    pub bytes: u64, //plaintext checked, not counting metadata or padding
+    pub metadata: Option<Metadata>,
+}
+
+struct CountingSink(u64); //throws away what it is given, only counting it
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// decrypts the whole file without writing plaintext anywhere, for checking that backups still open
+pub fn verify_file(input_path: &Path, credential: &Credential) -> Result<VerifyReport, CryptoError> {
+    let encrypted = open_encrypted(File::open(input_path)?)?;
+    let format = match &encrypted {
+        Encrypted::Versioned(header, _) => format!("FileEncryptor v{}, {}", header.version, header.algorithm.name()),
+        Encrypted::Unversioned(_) => String::from("FileEncryptor, unversioned"),
+        Encrypted::Age(_, _) => String::from("age v1"),
+        Encrypted::OpenSsl(_) => String::from("OpenSSL enc"),
+    };
+    let report = |files, bytes, metadata| Ok(VerifyReport { format: format.clone(), files, bytes, metadata });
+
+    match encrypted {
+        Encrypted::Versioned(header, _) if header.flags & header::FLAG_MIRROR != 0 => { //nothing but key slots
+            unlock(&header, credential)?;
+            report(0, 0, None)
+        },
+        Encrypted::Versioned(header, _) if header.flags & FLAG_CONTAINER != 0 => {
+            let (_, mut container) = open_container(input_path, credential)?;
+            let mut sink = CountingSink(0);
+            for name in container.entries.iter().map(|entry| entry.name.clone()).collect::<Vec<_>>() {
+                match container.extract(&name, &mut sink) {
+                    Err(CryptoError::Aead(_)) => return Err(CryptoError::Tampered),
+                    Err(CryptoError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(CryptoError::Tampered),
+                    result => result?,
+                }
+            }
+            report(container.entries.len(), sink.0, None)
+        },
+        Encrypted::Versioned(header, input) if header.flags & FLAG_ARCHIVE != 0 => {
+            let mut checked = (0, 0);
+            let metadata = decrypt_versioned(header, input, credential, |reader, _| {
+                checked = archive::check(reader)?;
+                Ok(())
+            })?;
+            report(checked.0, checked.1, metadata)
+        },
+        _ => {
+            let mut sink = CountingSink(0);
+            let metadata = decrypt(input_path, credential, &mut sink)?;
+            check_size(metadata.as_ref(), sink.0)?;
+            report(1, sink.0, metadata)
+        },
+    }
+}
+
+pub fn is_archive(input_path: &Path) -> Result<bool, CryptoError> { //whether the file holds a directory, which has to be extracted rather than decrypted
+    match open_encrypted(File::open(input_path)?)? {
+        Encrypted::Versioned(header, _) => Ok(header.flags & FLAG_ARCHIVE != 0),
+        _ => Ok(false),
+    }
+}
+
+pub fn extract_directory(input_path: &Path, output_dir: &Path, credential: &Credential) -> Result<Option<Metadata>, CryptoError> { //the tree goes into a temporary directory first and only appears once all of it checked out
+    let (header, input) = match open_encrypted(File::open(input_path)?)? {
+        Encrypted::Versioned(header, input) if header.flags & FLAG_ARCHIVE != 0 => (header, input),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "This file doesn't hold a directory").into()),
+    };
+    if output_dir.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", output_dir)).into());
+    }
+    let temp_dir = temp_path(output_dir)?;
+    create_private_dir(&temp_dir)?; //opened up to the archived permissions once the tree is unpacked
+
+    let result = decrypt_versioned(header, input, credential, |reader, metadata| {
+        archive::unpack(reader, &temp_dir, metadata.is_some_and(|metadata| metadata.owner.is_some())) //owners only come back when they were asked to be kept
+    });
+    match result.and_then(|metadata| Ok((fs::rename(&temp_dir, output_dir)?, metadata))) {
+        Ok(((), metadata)) => Ok(metadata),
+        Err(e) => {
+            let _ = fs::remove_dir_all(&temp_dir);
+            Err(e)
+        }
+    }
+}
+
+pub fn create_container(output_path: &Path, credentials: &[Credential], algorithm: Algorithm, kdf: Kdf) -> Result<(), CryptoError> { //an empty archive that files can be added to one at a time
+    let (header, key) = new_header(credentials, algorithm, kdf, EncryptOptions::default(), FLAG_CONTAINER)?;
+    let aad = header.associated_data()?;
+    write_atomically(output_path, |output| {
+        output.write_all(&header.to_bytes()?)?;
+        Container::create(output, header.algorithm, key, &aad)
+    })
+}
+
+fn open_container(path: &Path, credential: &Credential) -> Result<(Header, Container), CryptoError> {
+    let mut file = File::open(path)?;
+    let header = match open_encrypted(&mut file)? {
+        Encrypted::Versioned(header, _) if header.flags & FLAG_CONTAINER != 0 => header,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "This file isn't an archive of several files").into()),
+    };
+    let key = unlock(&header, credential)?;
+    let aad = header.associated_data()?;
+    let container = Container::open(file, header.algorithm, key, &aad).map_err(|e| match e { //the key is known to be right
+        CryptoError::Aead(_) => CryptoError::Tampered,
+        CryptoError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => CryptoError::Tampered,
+        e => e,
+    })?;
+    Ok((header, container))
+}
+
+pub fn is_container(input_path: &Path) -> Result<bool, CryptoError> {
+    match open_encrypted(File::open(input_path)?)? {
+        Encrypted::Versioned(header, _) => Ok(header.flags & FLAG_CONTAINER != 0),
+        _ => Ok(false),
+    }
+}
+
+pub fn list_container(path: &Path, credential: &Credential) -> Result<Vec<Entry>, CryptoError> { //only the index is decrypted
+    Ok(open_container(path, credential)?.1.entries)
+}
+
+pub fn extract_entry(path: &Path, credential: &Credential, name: &str, output_path: &Path) -> Result<(), CryptoError> {
+    let (_, mut container) = open_container(path, credential)?;
+    write_atomically(output_path, |output| match container.extract(name, output) {
+        Err(CryptoError::Aead(_)) => Err(CryptoError::Tampered),
+        Err(CryptoError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => Err(CryptoError::Tampered),
+        result => result,
+    })
+}
+
+pub fn add_entry(path: &Path, credential: &Credential, input_path: &Path) -> Result<(), CryptoError> { //stored under the file's name, the archive is rewritten with it
+    let name = input_path.file_name().and_then(|name| name.to_str()).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The file name isn't valid Unicode"))?;
+    let mut input = File::open(input_path)?;
+    let (header, container) = open_container(path, credential)?;
+    let header_bytes = header.to_bytes()?;
+    write_atomically(path, |output| container.add(name, &mut input, &header_bytes, output))
+}
+
+pub fn delete_entry(path: &Path, credential: &Credential, name: &str) -> Result<(), CryptoError> { //the archive is rewritten without it, the other entries are copied still sealed
+    let (header, container) = open_container(path, credential)?;
+    let header_bytes = header.to_bytes()?;
+    write_atomically(path, |output| container.remove(name, &header_bytes, output))
+}
+
+pub fn signature_path(path: &Path) -> PathBuf { //where a detached signature goes, next to the file
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+fn embedded_signature_hash(header: &Header, payload: &mut impl Read) -> Result<SignatureHasher, CryptoError> { //covers what the payload is authenticated with plus the payload
+    let mut hasher = SignatureHasher::new(b"embedded");
+    hasher.write_all(&header.associated_data()?)?;
+    io::copy(payload, &mut hasher)?;
+    Ok(hasher)
+}
+
+// embedded signatures go in the header of native files and survive changing the key slots, detached ones are a
+// .sig file next to any file, age and OpenSSL ones included, and cover every byte of it
+pub fn sign_file(path: &Path, identity: &SigningIdentity, detached: bool) -> Result<(), CryptoError> {
+    if detached {
+        let mut hasher = SignatureHasher::new(b"detached");
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        let signature = base64::engine::general_purpose::STANDARD.encode(hasher.sign(identity));
+        return write_atomically(&signature_path(path), |output| {
+            writeln!(output, "# signed by: {}", identity.to_signer())?;
+            writeln!(output, "{}", signature)?;
+            Ok(())
+        });
+    }
+
+    let mut input = File::open(path)?;
+    let mut header = match open_encrypted(&mut input)? {
+        Encrypted::Versioned(header, _) => header,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only native FileEncryptor files can hold a signature, sign it detached instead").into()),
+    };
+    let hasher = embedded_signature_hash(&header, &mut input)?;
+    header.set_extension(EXT_SIGNATURE, hasher.sign(identity));
+    let header_bytes = header.to_bytes()?;
+
+    let mut input = File::open(path)?;
+    open_encrypted(&mut input)?; //back to the start of the payload
+    write_atomically(path, |output| {
+        output.write_all(&header_bytes)?;
+        io::copy(&mut input, output)?;
+        Ok(())
+    })
+}
+
+pub fn verify_signature(path: &Path) -> Result<Signer, CryptoError> { //who signed the file, checking the embedded signature first
+    let mut input = File::open(path)?;
+    if let Ok(Encrypted::Versioned(header, _)) = open_encrypted(&mut input) {
+        if let Some(signature) = header.extension(EXT_SIGNATURE) {
+            return embedded_signature_hash(&header, &mut input)?.verify(signature).ok_or(CryptoError::BadSignature);
+        }
+    }
+
+    let text = match fs::read_to_string(signature_path(path)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(CryptoError::Unsigned),
+        Err(e) => return Err(e.into()),
+    };
+    let signature = text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(|line| base64::engine::general_purpose::STANDARD.decode(line).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No signature found in the .sig file"))?;
+    let mut hasher = SignatureHasher::new(b"detached");
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    hasher.verify(&signature).ok_or(CryptoError::BadSignature)
+}
+
+pub fn read_file(input_path: &Path, credential: &Credential) -> Result<String, CryptoError> {
+    let plaintext = String::from_utf8(decrypted_file(input_path, credential)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
     Ok(plaintext)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fileencryptor-test-{}-{}", std::process::id(), name))
+    }
+
+    fn read_header(path: &Path) -> Header {
+        let mut input = File::open(path).unwrap();
+        let mut magic = [0u8; MAGIC.len() + 1];
+        input.read_exact(&mut magic).unwrap();
+        Header::read_from(&mut input, magic[MAGIC.len()]).unwrap()
+    }
+
+    #[test]
+    fn scrypt_round_trip_for_every_algorithm() {
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        let plaintext: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+        let correct = Credential::Passphrase { passphrase: "correct horse".to_string(), keyfile: None };
+        let wrong = Credential::Passphrase { passphrase: "wrong horse".to_string(), keyfile: None };
+
+        for algorithm in Algorithm::all() {
+            let input = temp_path(&format!("scrypt-{}.txt", algorithm.identifier()));
+            let encrypted = temp_path(&format!("scrypt-{}.enc", algorithm.identifier()));
+            fs::write(&input, &plaintext).unwrap();
+
+            encrypt_file(&input, &encrypted, std::slice::from_ref(&correct), algorithm, kdf.clone(), EncryptOptions::default()).unwrap();
+            assert!(matches!(key_slots(&read_header(&encrypted)).as_slice(), [KeySlot::Passphrase { kdf: slot_kdf, .. }] if *slot_kdf == kdf));
+            assert_eq!(decrypted_file(&encrypted, &correct).unwrap(), plaintext);
+            assert!(decrypted_file(&encrypted, &wrong).is_err());
+
+            fs::remove_file(&input).unwrap();
+            fs::remove_file(&encrypted).unwrap();
+        }
+    }
+
+    #[test]
+    fn algorithm_identifiers_are_unique_and_round_trip() {
+        let ids: Vec<u8> = Algorithm::all().map(|algorithm| algorithm.identifier()).collect();
+        for (i, algorithm) in Algorithm::all().enumerate() {
+            assert!(!ids[..i].contains(&algorithm.identifier()), "{:?} reuses identifier {}", algorithm, algorithm.identifier());
+            assert_eq!(Algorithm::from_identifier(algorithm.identifier()), Some(algorithm));
+        }
+        assert!((0..=u8::MAX).filter(|id| !ids.contains(id)).all(|id| Algorithm::from_identifier(id).is_none()));
+    }
+
+    #[test]
+    fn unknown_algorithm_in_header_is_an_error() {
+        let input = temp_path("unknown-algorithm.txt");
+        let encrypted = temp_path("unknown-algorithm.enc");
+        fs::write(&input, b"no such cipher").unwrap();
+        let credential = Credential::Passphrase { passphrase: "unknown".to_string(), keyfile: None };
+        encrypt_file(&input, &encrypted, std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), Kdf::Scrypt { log_n: 10, r: 8, p: 1 }, EncryptOptions::default()).unwrap();
+
+        let mut bytes = fs::read(&encrypted).unwrap();
+        bytes[MAGIC.len() + 1] = 0xEE; //the algorithm byte
+        assert!(Header::read_from(&mut &bytes[MAGIC.len() + 1..], FORMAT_VERSION).is_err());
+        fs::write(&encrypted, &bytes).unwrap();
+        assert!(decrypted_file(&encrypted, &credential).is_err());
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn xchacha_files_carry_the_long_nonce() {
+        let xchacha = Algorithm::from_identifier(4).unwrap();
+        let input = temp_path("xchacha.txt");
+        let encrypted = temp_path("xchacha.enc");
+        fs::write(&input, b"192-bit nonce").unwrap();
+        let credential = Credential::Passphrase { passphrase: "extended".to_string(), keyfile: None };
+
+        encrypt_file(&input, &encrypted, std::slice::from_ref(&credential), xchacha, Kdf::Scrypt { log_n: 10, r: 8, p: 1 }, EncryptOptions::default()).unwrap();
+        let header = read_header(&encrypted);
+        assert_eq!(header.algorithm, xchacha);
+        assert_eq!(header.nonce.len(), xchacha.nonce_prefix_len());
+        assert_eq!(header.nonce.len() + stream::NONCE_SUFFIX_LEN, 24);
+        assert_eq!(fs::read(&encrypted).unwrap()[MAGIC.len() + 2] as usize, header.nonce.len()); //the nonce length byte
+        assert_eq!(decrypted_file(&encrypted, &credential).unwrap(), b"192-bit nonce");
+
+        let header_len = header.to_bytes().unwrap().len();
+        let mut short = header.clone();
+        short.nonce.truncate(Algorithm::chacha20_poly1305().nonce_prefix_len()); //the prefix a 96-bit nonce would have
+        let mut bytes = short.to_bytes().unwrap();
+        bytes.extend_from_slice(&fs::read(&encrypted).unwrap()[header_len..]);
+        fs::write(&encrypted, &bytes).unwrap();
+        assert!(decrypted_file(&encrypted, &credential).is_err());
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn keyfile_is_needed_next_to_the_passphrase() {
+        let input = temp_path("keyfile.txt");
+        let encrypted = temp_path("keyfile.enc");
+        let keyfile = temp_path("keyfile.key");
+        let other_keyfile = temp_path("keyfile-other.key");
+        fs::write(&input, b"needs both").unwrap();
+        fs::write(&keyfile, b"any file can be a keyfile").unwrap();
+        fs::write(&other_keyfile, b"but it has to be the same one").unwrap();
+        let with_keyfile = |keyfile: Option<&PathBuf>| Credential::Passphrase { passphrase: "both".to_string(), keyfile: keyfile.cloned() };
+
+        encrypt_file(&input, &encrypted, &[with_keyfile(Some(&keyfile))], Algorithm::chacha20_poly1305(), Kdf::Scrypt { log_n: 10, r: 8, p: 1 }, EncryptOptions::default()).unwrap();
+        assert_eq!(accepted_credentials(&encrypted).unwrap(), vec![CredentialKind::Passphrase { keyfile: true }]);
+        assert_eq!(decrypted_file(&encrypted, &with_keyfile(Some(&keyfile))).unwrap(), b"needs both");
+        assert!(matches!(decrypted_file(&encrypted, &with_keyfile(None)), Err(CryptoError::KeyfileRequired)));
+        assert!(matches!(decrypted_file(&encrypted, &with_keyfile(Some(&other_keyfile))), Err(CryptoError::WrongKey)));
+
+        for path in [input, encrypted, keyfile, other_keyfile] {
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn recipient_files_open_with_the_matching_identity() {
+        let input = temp_path("recipient.txt");
+        let encrypted = temp_path("recipient.enc");
+        fs::write(&input, b"for one reader").unwrap();
+        let identity = Identity::generate();
+        let other = Identity::generate();
+
+        encrypt_file(&input, &encrypted, &[Credential::Recipient(identity.to_recipient())], Algorithm::chacha20_poly1305(), Kdf::default(), EncryptOptions::default()).unwrap();
+        assert_eq!(accepted_credentials(&encrypted).unwrap(), vec![CredentialKind::Identity]);
+        assert_eq!(decrypted_file(&encrypted, &Credential::Identity(identity)).unwrap(), b"for one reader");
+        assert!(matches!(decrypted_file(&encrypted, &Credential::Identity(other)), Err(CryptoError::WrongKey)));
+        let passphrase = Credential::Passphrase { passphrase: "for one reader".to_string(), keyfile: None };
+        assert!(matches!(decrypted_file(&encrypted, &passphrase), Err(CryptoError::IdentityRequired)));
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn every_key_slot_unlocks_the_file() {
+        let input = temp_path("slots.txt");
+        let encrypted = temp_path("slots.enc");
+        fs::write(&input, b"shared").unwrap();
+        let identity = Identity::generate();
+        let credentials = [
+            Credential::Passphrase { passphrase: "first".to_string(), keyfile: None },
+            Credential::Passphrase { passphrase: "second".to_string(), keyfile: None },
+            Credential::Key([9u8; KEY_LEN]),
+            Credential::Recipient(identity.to_recipient()),
+        ];
+
+        encrypt_file(&input, &encrypted, &credentials, Algorithm::chacha20_poly1305(), Kdf::Scrypt { log_n: 10, r: 8, p: 1 }, EncryptOptions::default()).unwrap();
+        assert_eq!(key_slots(&read_header(&encrypted)).len(), credentials.len());
+        assert_eq!(accepted_credentials(&encrypted).unwrap(), vec![CredentialKind::Passphrase { keyfile: false }, CredentialKind::Key, CredentialKind::Identity]);
+        for credential in [credentials[0].clone(), credentials[1].clone(), credentials[2].clone(), Credential::Identity(identity)] {
+            assert_eq!(decrypted_file(&encrypted, &credential).unwrap(), b"shared", "{:?}", credential);
+        }
+        assert!(matches!(decrypted_file(&encrypted, &Credential::Passphrase { passphrase: "third".to_string(), keyfile: None }), Err(CryptoError::WrongKey)));
+        assert!(matches!(decrypted_file(&encrypted, &Credential::Key([8u8; KEY_LEN])), Err(CryptoError::WrongKey)));
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn deflate_shrinks_text_and_skips_compressed_input() {
+        let input = temp_path("deflate.txt");
+        let encrypted = temp_path("deflate.enc");
+        let credential = Credential::Key([5u8; KEY_LEN]);
+        let options = EncryptOptions { compression: Some(Compression::deflate()), ..EncryptOptions::default() };
+        let text = b"the same line over and over\n".repeat(4096);
+        let gzip = [&b"\x1f\x8b\x08\x00"[..], &text].concat(); //only the magic matters for the check
+
+        for (plaintext, compressed) in [(text, true), (gzip, false)] {
+            fs::write(&input, &plaintext).unwrap();
+            encrypt_file(&input, &encrypted, std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), Kdf::None, options).unwrap();
+            let header = read_header(&encrypted);
+            assert_eq!(header.extension(EXT_COMPRESSION).map(Compression::from_bytes).transpose().unwrap(), compressed.then(Compression::deflate));
+            assert_eq!(fs::metadata(&encrypted).unwrap().len() < plaintext.len() as u64 / 10, compressed);
+            assert_eq!(decrypted_file(&encrypted, &credential).unwrap(), plaintext);
+        }
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn signatures_verify_and_catch_changes() {
+        let credential = Credential::Key([6u8; KEY_LEN]);
+        let identity = SigningIdentity::generate();
+        let other = SigningIdentity::generate();
+        let (path, bytes, header_len, _) = segmented_file("signed", &credential);
+
+        sign_file(&path, &identity, false).unwrap();
+        assert_eq!(verify_signature(&path).unwrap(), identity.to_signer());
+        assert_ne!(verify_signature(&path).unwrap(), other.to_signer()); //it is up to the caller to expect the right key
+        assert!(decrypted_file(&path, &credential).is_ok()); //the signature is outside the associated data
+        let signed = fs::read(&path).unwrap();
+        let mut header = read_header(&path);
+        let signed_header_len = header.to_bytes().unwrap().len();
+        assert_eq!(signed[signed_header_len..], bytes[header_len..]);
+
+        let mut tampered = signed.clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        fs::write(&path, &tampered).unwrap();
+        assert!(matches!(verify_signature(&path), Err(CryptoError::BadSignature)));
+
+        header.add_extension(0x7000, b"added later".to_vec());
+        fs::write(&path, [header.to_bytes().unwrap(), signed[signed_header_len..].to_vec()].concat()).unwrap();
+        assert!(matches!(verify_signature(&path), Err(CryptoError::BadSignature)));
+
+        fs::write(&path, &signed).unwrap();
+        let ours = read_header(&path).extension(EXT_SIGNATURE).unwrap().to_vec();
+        sign_file(&path, &other, false).unwrap();
+        assert_eq!(verify_signature(&path).unwrap(), other.to_signer());
+        let mut header = read_header(&path);
+        let forged = [&header.extension(EXT_SIGNATURE).unwrap()[..signing::PUBLIC_KEY_LEN], &ours[signing::PUBLIC_KEY_LEN..]].concat(); //claims the other key made our signature
+        header.set_extension(EXT_SIGNATURE, forged);
+        fs::write(&path, [header.to_bytes().unwrap(), signed[signed_header_len..].to_vec()].concat()).unwrap();
+        assert!(matches!(verify_signature(&path), Err(CryptoError::BadSignature)));
+
+        fs::write(&path, &bytes).unwrap(); //unsigned again, so the detached signature is what counts
+        assert!(matches!(verify_signature(&path), Err(CryptoError::Unsigned)));
+        sign_file(&path, &identity, true).unwrap();
+        assert_eq!(verify_signature(&path).unwrap(), identity.to_signer());
+        let mut tampered = bytes.clone();
+        tampered[header_len] ^= 0x01;
+        fs::write(&path, &tampered).unwrap();
+        assert!(matches!(verify_signature(&path), Err(CryptoError::BadSignature)));
+
+        fs::remove_file(signature_path(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn signed_containers_still_open() {
+        let credential = Credential::Key([3u8; KEY_LEN]);
+        let identity = SigningIdentity::generate();
+        let (archive, entry, extracted) = (temp_path("signed.fenc"), temp_path("signed-entry.txt"), temp_path("signed-extracted.txt"));
+        fs::write(&entry, b"inside a signed archive").unwrap();
+        create_container(&archive, std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), Kdf::None).unwrap();
+        add_entry(&archive, &credential, &entry).unwrap();
+
+        sign_file(&archive, &identity, false).unwrap();
+        assert_eq!(verify_signature(&archive).unwrap(), identity.to_signer());
+        let name = entry.file_name().unwrap().to_str().unwrap();
+        extract_entry(&archive, &credential, name, &extracted).unwrap();
+        assert_eq!(fs::read(&extracted).unwrap(), b"inside a signed archive");
+
+        add_entry(&archive, &credential, &extracted).unwrap(); //adding a file changes what was signed
+        assert!(matches!(verify_signature(&archive), Err(CryptoError::BadSignature)));
+
+        for path in [archive, entry, extracted] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn verify_reports_what_it_checked() {
+        let credential = Credential::Key([2u8; KEY_LEN]);
+        let algorithm = Algorithm::chacha20_poly1305();
+        let format = format!("FileEncryptor v{}, {}", FORMAT_VERSION, algorithm.name());
+        let (folder, single, archive, container) = (temp_path("verify-folder"), temp_path("verify.enc"), temp_path("verify-folder.fenc"), temp_path("verify-container.fenc"));
+        fs::create_dir(&folder).unwrap();
+        let (first, second) = (folder.join("first.txt"), folder.join("second.txt"));
+        fs::write(&first, b"twelve bytes").unwrap();
+        fs::write(&second, b"and twenty-one more.").unwrap();
+
+        encrypt_file(&first, &single, std::slice::from_ref(&credential), algorithm, Kdf::None, EncryptOptions { padding: Some(Padding::PowerOfTwo), ..EncryptOptions::default() }).unwrap();
+        let report = verify_file(&single, &credential).unwrap();
+        assert_eq!((report.format.as_str(), report.files, report.bytes), (format.as_str(), 1, 12)); //the padding isn't counted
+        let metadata = report.metadata.unwrap();
+        assert_eq!((metadata.name.as_deref(), metadata.size), (Some(std::ffi::OsStr::new("first.txt")), Some(12)));
+
+        encrypt_directory(&folder, &archive, std::slice::from_ref(&credential), algorithm, Kdf::None, EncryptOptions::default()).unwrap();
+        let report = verify_file(&archive, &credential).unwrap();
+        assert_eq!((report.files, report.bytes), (3, 32)); //the folder itself and its two files
+
+        create_container(&container, std::slice::from_ref(&credential), algorithm, Kdf::None).unwrap();
+        add_entry(&container, &credential, &first).unwrap();
+        add_entry(&container, &credential, &second).unwrap();
+        let report = verify_file(&container, &credential).unwrap();
+        assert_eq!((report.files, report.bytes, report.metadata.is_none()), (2, 32, true));
+
+        for path in [single, archive, container] {
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn upgrading_changes_only_what_is_stale() {
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        let stronger = Kdf::Scrypt { log_n: 11, r: 8, p: 1 };
+        let chacha = Algorithm::chacha20_poly1305();
+        let other = Algorithm::all().find(|algorithm| *algorithm != chacha).unwrap();
+        let passphrase = Credential::Passphrase { passphrase: "upgrade".to_string(), keyfile: None };
+        let wrong = Credential::Passphrase { passphrase: "downgrade".to_string(), keyfile: None };
+        let key = Credential::Key([1u8; KEY_LEN]);
+        let (input, path) = (temp_path("upgrade.txt"), temp_path("upgrade.enc"));
+        fs::write(&input, b"upgrade me").unwrap();
+        encrypt_file(&input, &path, &[passphrase.clone(), key.clone()], chacha, kdf.clone(), EncryptOptions::default()).unwrap();
+
+        assert!(matches!(upgrade_file(&path, &wrong, chacha, kdf.clone()), Err(CryptoError::WrongKey))); //not "already up to date"
+        assert!(!upgrade_file(&path, &passphrase, chacha, kdf.clone()).unwrap());
+        assert!(upgrade_file(&path, &passphrase, chacha, stronger.clone()).unwrap()); //only the passphrase slot changes
+        assert!(matches!(key_slots(&read_header(&path)).as_slice(), [KeySlot::Key { .. }, KeySlot::Passphrase { kdf: slot_kdf, .. }] if *slot_kdf == stronger));
+
+        assert!(upgrade_file(&path, &key, other, stronger.clone()).unwrap());
+        let header = read_header(&path);
+        assert_eq!(header.algorithm, other);
+        assert!(matches!(key_slots(&header).as_slice(), [KeySlot::Key { .. }, KeySlot::Passphrase { kdf: slot_kdf, .. }] if *slot_kdf == stronger));
+        for credential in [&passphrase, &key] {
+            assert_eq!(decrypted_file(&path, credential).unwrap(), b"upgrade me");
+        }
+        assert_eq!(create_decrypted_file(&path, &temp_path("upgrade-out.txt"), &key).unwrap().and_then(|metadata| metadata.name), input.file_name().map(Into::into)); //the metadata came along
+        fs::remove_file(temp_path("upgrade-out.txt")).unwrap();
+
+        sign_file(&path, &SigningIdentity::generate(), false).unwrap();
+        let signed = fs::read(&path).unwrap();
+        assert!(upgrade_file(&path, &key, chacha, stronger.clone()).is_err()); //would lose the signature
+        assert_eq!(fs::read(&path).unwrap(), signed);
+
+        fs::write(&path, hex::decode("0343335abc8c59ceeabe4b21dfda0ce612e34a811b10950329d122b1f1126a29163808a2bbba0bbc892b71a24fa47f6686156b118a54922a5071463645864df22ca7038adbc50cdbf92dbf10729c64").unwrap()).unwrap(); //from decrypts_files_from_before_the_header
+        let baseline = Credential::Passphrase { passphrase: "baseline".to_string(), keyfile: None };
+        assert!(upgrade_file(&path, &baseline, chacha, kdf.clone()).unwrap());
+        assert_eq!(read_header(&path).version, FORMAT_VERSION);
+        assert_eq!(decrypted_file(&path, &baseline).unwrap(), b"written before the header existed\n");
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn upgrading_a_folder_counts_every_file() {
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        let chacha = Algorithm::chacha20_poly1305();
+        let other = Algorithm::all().find(|algorithm| *algorithm != chacha).unwrap();
+        let key = Credential::Key([7u8; KEY_LEN]);
+        let folder = temp_path("upgrade-folder");
+        fs::create_dir_all(folder.join("nested")).unwrap();
+        let plain = folder.join("plain.txt");
+        fs::write(&plain, b"not encrypted").unwrap();
+        for (name, algorithm, credential) in [("old.enc", chacha, &key), ("nested/current.enc", other, &key), ("someone-else.enc", chacha, &Credential::Key([8u8; KEY_LEN]))] {
+            encrypt_file(&plain, &folder.join(name), std::slice::from_ref(credential), algorithm, kdf.clone(), EncryptOptions::default()).unwrap();
+        }
+
+        let report = upgrade_directory(&folder, &key, other, kdf.clone()).unwrap();
+        assert_eq!((report.upgraded, report.current, report.skipped), (1, 1, 1));
+        assert!(matches!(report.failed.as_slice(), [(path, CryptoError::WrongKey)] if *path == folder.join("someone-else.enc")));
+        assert_eq!(read_header(&folder.join("old.enc")).algorithm, other);
+        assert_eq!(decrypted_file(&folder.join("old.enc"), &key).unwrap(), b"not encrypted");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn upgrading_a_keyfile_folder_asks_for_the_keyfile() {
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        let chacha = Algorithm::chacha20_poly1305();
+        let other = Algorithm::all().find(|algorithm| *algorithm != chacha).unwrap();
+        let folder = temp_path("upgrade-keyfile-folder");
+        let keyfile = temp_path("upgrade-keyfile.key");
+        fs::create_dir_all(folder.join("nested")).unwrap();
+        fs::write(&keyfile, b"locks the whole folder").unwrap();
+        let plain = folder.join("plain.txt");
+        fs::write(&plain, b"needs the keyfile").unwrap();
+        let with_keyfile = |keyfile: Option<&PathBuf>| Credential::Passphrase { passphrase: "folder".to_string(), keyfile: keyfile.cloned() };
+        for name in ["first.enc", "nested/second.enc"] {
+            encrypt_file(&plain, &folder.join(name), &[with_keyfile(Some(&keyfile))], chacha, kdf.clone(), EncryptOptions::default()).unwrap();
+        }
+
+        let sample = first_upgradable(&folder).unwrap().unwrap();
+        assert_eq!(accepted_credentials(&sample).unwrap(), vec![CredentialKind::Passphrase { keyfile: true }]);
+        let report = upgrade_directory(&folder, &with_keyfile(None), other, kdf.clone()).unwrap();
+        assert_eq!(report.upgraded, 0);
+        assert!(report.failed.len() == 2 && report.failed.iter().all(|(_, e)| matches!(e, CryptoError::KeyfileRequired)));
+
+        let report = upgrade_directory(&folder, &with_keyfile(Some(&keyfile)), other, kdf.clone()).unwrap();
+        assert_eq!((report.upgraded, report.current, report.skipped, report.failed.len()), (2, 0, 1, 0));
+        for name in ["first.enc", "nested/second.enc"] {
+            assert_eq!(read_header(&folder.join(name)).algorithm, other);
+            assert_eq!(decrypted_file(&folder.join(name), &with_keyfile(Some(&keyfile))).unwrap(), b"needs the keyfile");
+        }
+        fs::remove_dir_all(&folder).unwrap();
+        fs::remove_file(&keyfile).unwrap();
+    }
+
+    #[test]
+    fn any_changed_header_byte_fails() {
+        let credential = Credential::Key([7u8; KEY_LEN]); //no KDF, whose parameters a flipped bit could make very expensive
+        let input = temp_path("header.txt");
+        let path = temp_path("header.enc");
+        fs::write(&input, b"header tampering").unwrap();
+        encrypt_file(&input, &path, std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), Kdf::default(), EncryptOptions::default()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let mut header = read_header(&path);
+        let header_len = header.to_bytes().unwrap().len();
+
+        for position in MAGIC.len()..header_len {
+            let mut tampered = bytes.clone();
+            tampered[position] ^= 0x01;
+            fs::write(&path, &tampered).unwrap();
+            assert!(decrypted_file(&path, &credential).is_err(), "changing header byte {} went unnoticed", position);
+        }
+
+        header.set_extension(0x7000, b"injected".to_vec()); //an extension this version skips, so only the associated data can catch it
+        let mut tampered = header.to_bytes().unwrap();
+        tampered.extend_from_slice(&bytes[header_len..]);
+        fs::write(&path, &tampered).unwrap();
+        assert!(matches!(decrypted_file(&path, &credential), Err(CryptoError::Tampered)));
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn decrypts_fixed_age_files() { //an X25519 and a passphrase file for the same payload, made outside this crate from the age v1 spec
+        let payload = hex::decode("101112131415161718191a1b1c1d1e1ff6dc51aa12a97c84f4b67b4811afcdd4b24ea17fa705ba4e3336a1f17c36").unwrap();
+        let to_identity = b"age-encryption.org/v1\n\
+            -> X25519 eaYx7t4b+cmPEgMs3q3Q56B5OY/HhriMyEbsia+FpRo\n\
+            8UVANqZ+15ieLhAXPQi3BGtpo0Pk4oYsUUZG7a/DVDk\n\
+            --- 2jMMZ8wpbAASIYSIw/4uBLNVmi8HIYUYuD5wA8eZpZg\n";
+        let to_passphrase = b"age-encryption.org/v1\n\
+            -> scrypt YGFiY2RlZmdoaWprbG1ubw 10\n\
+            fytMFyhy2NbFER/nrLWuc8UVRVXPbRfUKARbhZw7zBM\n\
+            --- N8Xu7eiBdfpyqkCf3nfvALvHqO6cW3NoSX0oHrTpo5w\n";
+        let identity = Identity::parse("AGE-SECRET-KEY-1YQSJYGEYY5NZW2PF9G4JCTFW9UCRZV3NXS6NVDEC8YARK0PA8CLSWXGG7Q").unwrap();
+        let passphrase = Credential::Passphrase { passphrase: "age passphrase".to_string(), keyfile: None };
+
+        let path = temp_path("fixed.age");
+        for (header, credential) in [(&to_identity[..], Credential::Identity(identity)), (&to_passphrase[..], passphrase)] {
+            fs::write(&path, [header, &payload].concat()).unwrap();
+            assert_eq!(decrypted_file(&path, &credential).unwrap(), b"sent with age\n");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn decrypts_fixed_openssl_file() { //`openssl enc -aes-256-cbc -pbkdf2 -salt -pass pass:legacy` of "openssl vector\n", OpenSSL 3.5
+        let path = temp_path("fixed.openssl");
+        fs::write(&path, hex::decode("53616c7465645f5fa51908bce60a76615955ca048245a820fa2640ebaba4c5dc").unwrap()).unwrap();
+        let correct = Credential::Passphrase { passphrase: "legacy".to_string(), keyfile: None };
+        let wrong = Credential::Passphrase { passphrase: "modern".to_string(), keyfile: None };
+        assert_eq!(decrypted_file(&path, &correct).unwrap(), b"openssl vector\n");
+        assert!(decrypted_file(&path, &wrong).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn padded_round_trip_keeps_exact_length() {
+        let key = Credential::Key([3u8; KEY_LEN]);
+        let input = temp_path("padded.txt");
+        let encrypted = temp_path("padded.enc");
+        let decrypted = temp_path("padded.out");
+        for padding in [Padding::Padme, Padding::PowerOfTwo] {
+            for len in [0, 1, 1000, stream::SEGMENT_LEN - 1, stream::SEGMENT_LEN, stream::SEGMENT_LEN + 1, 200_003] {
+                let mut plaintext: Vec<u8> = (0..len as u32).map(|i| (i % 253) as u8).collect();
+                if let [.., marker, zero] = plaintext.as_mut_slice() { //looks like padding itself
+                    (*marker, *zero) = (0x80, 0);
+                }
+                fs::write(&input, &plaintext).unwrap();
+                for compression in [None, Some(Compression::zstd())] {
+                    let options = EncryptOptions { padding: Some(padding), compression, ..Default::default() };
+                    encrypt_file(&input, &encrypted, std::slice::from_ref(&key), Algorithm::chacha20_poly1305(), Kdf::default(), options).unwrap();
+                    assert_eq!(decrypted_file(&encrypted, &key).unwrap(), plaintext, "{:?} {} bytes", padding, len);
+                    create_decrypted_file(&encrypted, &decrypted, &key).unwrap();
+                    assert_eq!(fs::metadata(&decrypted).unwrap().len(), len as u64);
+                }
+            }
+        }
+        for path in [input, encrypted, decrypted] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn wrong_sealed_size_is_tampering() {
+        let credential = Credential::Passphrase { passphrase: "size".to_string(), keyfile: None };
+        let input = temp_path("size.txt");
+        let encrypted = temp_path("size.enc");
+        let decrypted = temp_path("size.out");
+        fs::write(&input, b"twelve bytes").unwrap();
+        let metadata = Metadata { size: Some(13), ..Metadata::from_file(&input, false).unwrap() };
+        let (header, key) = new_header(std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), Kdf::Scrypt { log_n: 10, r: 8, p: 1 }, EncryptOptions::default(), FLAG_METADATA).unwrap();
+        seal(&encrypted, &header, &key, EncryptOptions::default(), &metadata, |writer| writer.write_all(b"twelve bytes")).unwrap();
+
+        assert!(matches!(create_decrypted_file(&encrypted, &decrypted, &credential), Err(CryptoError::Tampered)));
+        assert!(!decrypted.exists());
+        assert!(matches!(verify_file(&encrypted, &credential), Err(CryptoError::Tampered)));
+        assert!(matches!(decrypted_file(&encrypted, &credential), Err(CryptoError::Tampered)));
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn debug_never_shows_secrets() {
+        let passphrase = Credential::Passphrase { passphrase: "hunter2".to_string(), keyfile: None };
+        let key = Credential::Key([0xab; KEY_LEN]);
+        assert!(!format!("{:?}", passphrase).contains("hunter2"));
+        assert_eq!(format!("{:?}", key), "Key(<redacted>)");
+    }
+
+    #[test]
+    fn key_files_read_back() {
+        let (identity_path, signing_path) = (temp_path("identity.txt"), temp_path("signing.txt"));
+        let recipient = generate_identity_file(&identity_path).unwrap();
+        let signer = generate_signing_key_file(&signing_path).unwrap();
+        assert_eq!(read_identity_file(&identity_path).unwrap().to_recipient(), recipient);
+        assert_eq!(read_signing_key_file(&signing_path).unwrap().to_signer(), signer);
+        assert!(read_identity_file(&signing_path).is_err()); //the other kind of key is not mistaken for one
+        assert!(read_signing_key_file(&identity_path).is_err());
+        fs::remove_file(identity_path).unwrap();
+        fs::remove_file(signing_path).unwrap();
+    }
+
+    #[test]
+    fn decrypts_files_from_before_the_header() { //"written before the header existed\n" encrypted by the baseline code with the passphrase "baseline"
+        let path = temp_path("fixed.v0");
+        let correct = Credential::Passphrase { passphrase: "baseline".to_string(), keyfile: None };
+        let wrong = Credential::Passphrase { passphrase: "header".to_string(), keyfile: None };
+        for vector in [
+            "017166a51e8de59b396b52412b84dc2f09fbfb24e3e9e15bc1948281003146f6c36dac810b00fe019dff50fbc6acd7c392abfe65a62cc324e0dfd4aa9e6b3ee22411a11e23dc14970b2dad40610bfd", //AES-256-GCM
+            "0241e0d5c159f8b7f3c0a4f22a815a0420a036d926175f0dd4ec994e7d15b99356e2f48d19e33b7154675a7eba4ceef1c608a1d5fe49562147181cf6a7a7ae2fbb60b8dc909fcf57091690f3810109", //AES-256-GCM-SIV
+            "0343335abc8c59ceeabe4b21dfda0ce612e34a811b10950329d122b1f1126a29163808a2bbba0bbc892b71a24fa47f6686156b118a54922a5071463645864df22ca7038adbc50cdbf92dbf10729c64", //ChaCha20-Poly1305
+        ] {
+            fs::write(&path, hex::decode(vector).unwrap()).unwrap();
+            assert_eq!(decrypted_file(&path, &correct).unwrap(), b"written before the header existed\n");
+            assert!(decrypted_file(&path, &wrong).is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn inspect_tells_damaged_headers_from_other_files() {
+        use inspect::Inspection;
+        let path = temp_path("inspect.bin");
+        let (encrypted, bytes, header_len, _) = segmented_file("inspect", &Credential::Key([1u8; KEY_LEN]));
+        fs::write(&path, &bytes[..header_len - 1]).unwrap(); //cut inside the header
+        assert!(matches!(inspect_file(&path), Err(CryptoError::Io(e)) if e.kind() == io::ErrorKind::InvalidData));
+        assert!(matches!(decrypted_file(&path, &Credential::Key([1u8; KEY_LEN])), Err(CryptoError::Io(e)) if e.kind() == io::ErrorKind::InvalidData));
+
+        for (first, unversioned) in [(1u8, true), (3, true), (0, false), (4, false), (0x83, false), (b'#', false)] {
+            fs::write(&path, [&[first], &[0u8; 100][..]].concat()).unwrap();
+            assert_eq!(matches!(inspect_file(&path).unwrap(), Inspection::Unversioned { .. }), unversioned, "first byte {:#x}", first);
+        }
+        for (len, unversioned) in [(1 + SALT_LEN + NONCE_LEN + 16, true), (1 + SALT_LEN + NONCE_LEN + 15, false), (5, false)] { //an empty plaintext still leaves its tag
+            fs::write(&path, [&[2u8], &vec![0u8; len - 1][..]].concat()).unwrap();
+            assert_eq!(matches!(inspect_file(&path).unwrap(), Inspection::Unversioned { .. }), unversioned, "{} bytes", len);
+        }
+        fs::write(&path, [&[2u8], &[7u8; SALT_LEN][..], &[0u8; NONCE_LEN + 16][..]].concat()).unwrap();
+        assert!(matches!(inspect_file(&path).unwrap(), Inspection::Unversioned { salt, .. } if salt == [7u8; SALT_LEN]));
+        fs::write(&path, b"short").unwrap();
+        assert!(matches!(inspect_file(&path).unwrap(), Inspection::NotEncrypted));
+
+        let Inspection::Native(info) = inspect_file(&encrypted).unwrap() else { panic!("not read as a native file") };
+        let slots = key_slots(&read_header(&encrypted));
+        let [KeySlot::Key { salt, .. }] = slots.as_slice() else { panic!("not a single raw key slot") };
+        assert_eq!(info.salts, [(CredentialKind::Key, salt.clone())]);
+        assert!(Inspection::Native(info).to_string().contains(&hex::encode(salt)));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+
+    #[test]
+    fn absurd_kdf_cost_is_refused() {
+        let credential = Credential::Passphrase { passphrase: "cost".to_string(), keyfile: None };
+        let (path, bytes, header_len, _) = segmented_file("kdf-cost", &credential);
+        let mut header = read_header(&path);
+        let Some(KeySlot::Passphrase { salt, keyfile, wrapped, .. }) = KeySlot::parse(header.extension(EXT_KEY_SLOT).unwrap()) else { panic!("not a passphrase slot") };
+        let crafted_slot = KeySlot::Passphrase { kdf: Kdf::Pbkdf2 { iterations: u32::MAX }, salt, keyfile, wrapped }.to_bytes(); //would take hours before the wrong passphrase is even noticed
+        assert!(KeySlot::parse(&crafted_slot).is_none());
+        header.set_extension(EXT_KEY_SLOT, crafted_slot);
+        let mut crafted = header.to_bytes().unwrap();
+        crafted.extend_from_slice(&bytes[header_len..]);
+        fs::write(&path, &crafted).unwrap();
+        assert!(decrypted_file(&path, &credential).is_err());
+        fs::remove_file(&path).unwrap();
+
+        for kdf in [
+            Kdf::Argon2id { memory_kib: 4 * 1024 * 1024, iterations: 1_000, parallelism: 4 }, //each within its limit, together thousands of GiB to fill
+            Kdf::Scrypt { log_n: 22, r: 8, p: 8 }, //4 GiB is allowed, but not mixed eight times over
+        ] {
+            assert!(Kdf::from_identifier(kdf.identifier(), &kdf.params()).is_none(), "{:?}", kdf);
+        }
+        for kdf in [Kdf::argon2id(), Kdf::scrypt(), Kdf::pbkdf2(), Kdf::Scrypt { log_n: 22, r: 8, p: 1 }] {
+            assert!(kdf.is_sane(), "{:?}", kdf);
+        }
+    }
+
+    #[test]
+    fn calibration_scales_with_the_target() {
+        use std::time::Duration;
+        fn cost(kdf: &Kdf) -> u64 {
+            match kdf {
+                Kdf::None => 0,
+                Kdf::Pbkdf2 { iterations } => *iterations as u64,
+                Kdf::Argon2id { memory_kib, iterations, .. } => *memory_kib as u64 * *iterations as u64,
+                Kdf::Scrypt { log_n, r, p } => (*r as u64 * *p as u64) << *log_n,
+            }
+        }
+        for kdf in [Kdf::pbkdf2(), Kdf::argon2id(), Kdf::scrypt()] {
+            let costs: Vec<u64> = [10, 100, 1_000].into_iter().map(|millis| {
+                let calibrated = kdf.calibrate(Duration::from_millis(millis)).unwrap();
+                assert!(calibrated.is_sane(), "{:?}", calibrated);
+                assert_eq!(calibrated.identifier(), kdf.identifier());
+                cost(&calibrated)
+            }).collect();
+            assert!(costs.windows(2).all(|pair| pair[0] <= pair[1]), "{:?} costs {:?}", kdf, costs);
+            assert!(costs[0] < costs[2], "{:?} costs {:?}", kdf, costs); //a hundred times the target is never lost to timing noise
+        }
+    }
+
+    #[test]
+    fn cutting_a_container_short_never_rolls_it_back() {
+        let credential = Credential::Passphrase { passphrase: "archive".to_string(), keyfile: None };
+        let archive = temp_path("container.fenc");
+        let (first, second) = (temp_path("container-first.txt"), temp_path("container-second.txt"));
+        fs::write(&first, b"first entry").unwrap();
+        fs::write(&second, b"second entry").unwrap();
+        create_container(&archive, std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), Kdf::Scrypt { log_n: 10, r: 8, p: 1 }).unwrap();
+        add_entry(&archive, &credential, &first).unwrap();
+        let before = fs::read(&archive).unwrap().len() as u64;
+        add_entry(&archive, &credential, &second).unwrap();
+        let second_name = second.file_name().unwrap().to_str().unwrap();
+        delete_entry(&archive, &credential, first.file_name().unwrap().to_str().unwrap()).unwrap();
+        let entries = list_container(&archive, &credential).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), [second_name]);
+
+        add_entry(&archive, &credential, &first).unwrap();
+        let length = fs::read(&archive).unwrap().len() as u64;
+        for cut in [before, length - 1, length / 2] { //no earlier index is left behind to fall back to
+            File::options().write(true).open(&archive).unwrap().set_len(cut).unwrap();
+            assert!(list_container(&archive, &credential).is_err());
+        }
+
+        for path in [archive, first, second] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn aes_siv_matches_rfc5297() { //A.1 and A.2, the nonce in A.2 being the last associated data
+        use aes_siv::siv::Aes128Siv;
+        use aes_siv::KeyInit;
+        let mut deterministic = Aes128Siv::new_from_slice(&hex::decode("fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").unwrap()).unwrap();
+        let associated = hex::decode("101112131415161718191a1b1c1d1e1f2021222324252627").unwrap();
+        let ciphertext = deterministic.encrypt([&associated], &hex::decode("112233445566778899aabbccddee").unwrap()).unwrap();
+        assert_eq!(hex::encode(ciphertext), "85632d07c6e8f37f950acd320a2ecc9340c02b9690c4dc04daef7f6afe5c");
+
+        let mut nonce_based = Aes128Siv::new_from_slice(&hex::decode("7f7e7d7c7b7a79787776757473727170404142434445464748494a4b4c4d4e4f").unwrap()).unwrap();
+        let associated = [
+            hex::decode("00112233445566778899aabbccddeeffdeaddadadeaddadaffeeddccbbaa99887766554433221100").unwrap(),
+            hex::decode("102030405060708090a0").unwrap(),
+            hex::decode("09f911029d74e35bd84156c5635688c0").unwrap(),
+        ];
+        let ciphertext = nonce_based.encrypt(&associated, b"this is some plaintext to encrypt using SIV-AES").unwrap();
+        assert_eq!(
+            hex::encode(&ciphertext),
+            "7bdb6e3b432667eb06f4d14bff2fbd0fcb900f2fddbe404326601965c889bf17dba77ceb094fa663b7a3f748ba8af829ea64ad544a272e9c485b62a3fd5c0d"
+        );
+        assert!(nonce_based.decrypt(&associated[..2], &ciphertext).is_err());
+    }
+
+    #[test]
+    fn mirror_notices_size_changes_and_skips_long_names() {
+        let key = Credential::Key([9u8; KEY_LEN]);
+        let (source, mirror, restored) = (temp_path("mirror-source"), temp_path("mirror-copy"), temp_path("mirror-restored"));
+        fs::create_dir(&source).unwrap();
+        let file = source.join("notes.txt");
+        let long = source.join("n".repeat(200));
+        fs::write(&file, b"first").unwrap();
+        fs::write(&long, b"never mirrored").unwrap();
+        create_mirror(&mirror, std::slice::from_ref(&key), Algorithm::chacha20_poly1305(), Kdf::default()).unwrap();
+
+        let report = sync_mirror(&source, &mirror, &key, EncryptOptions::default()).unwrap();
+        assert_eq!((report.encrypted, report.skipped, report.too_long), (1, 0, vec![long.clone()])); //counted once, as too long
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        fs::write(&file, b"second, longer").unwrap();
+        File::options().write(true).open(&file).unwrap().set_modified(modified).unwrap(); //same time, only the size gives it away
+        assert_eq!(sync_mirror(&source, &mirror, &key, EncryptOptions::default()).unwrap().encrypted, 1);
+        assert_eq!(sync_mirror(&source, &mirror, &key, EncryptOptions::default()).unwrap().unchanged, 1);
+
+        assert_eq!(restore_mirror(&mirror, &restored, &key).unwrap(), 1);
+        assert_eq!(fs::read(restored.join("notes.txt")).unwrap(), b"second, longer");
+        for path in [source, mirror, restored] {
+            fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn swapping_mirrored_files_is_tampering() {
+        let key = Credential::Key([4u8; KEY_LEN]);
+        let (source, mirror, restored) = (temp_path("swap-source"), temp_path("swap-copy"), temp_path("swap-restored"));
+        for (folder, content) in [("a", "in a"), ("b", "in b")] { //the same name in both, so only the path tells them apart
+            fs::create_dir_all(source.join(folder)).unwrap();
+            fs::write(source.join(folder).join("same.txt"), content).unwrap();
+        }
+        create_mirror(&mirror, std::slice::from_ref(&key), Algorithm::chacha20_poly1305(), Kdf::default()).unwrap();
+        sync_mirror(&source, &mirror, &key, EncryptOptions::default()).unwrap();
+        assert_eq!(restore_mirror(&mirror, &restored, &key).unwrap(), 2);
+        fs::remove_dir_all(&restored).unwrap();
+
+        let copies: Vec<PathBuf> = fs::read_dir(&mirror).unwrap().map(|entry| entry.unwrap().path()).filter(|path| path.is_dir())
+            .map(|folder| fs::read_dir(folder).unwrap().next().unwrap().unwrap().path()).collect();
+        let (first, second) = (fs::read(&copies[0]).unwrap(), fs::read(&copies[1]).unwrap());
+        fs::write(&copies[0], &second).unwrap();
+        fs::write(&copies[1], &first).unwrap();
+        assert!(matches!(restore_mirror(&mirror, &restored, &key), Err(CryptoError::Tampered)));
+
+        for path in [source, mirror, restored] {
+            let _ = fs::remove_dir_all(path);
+        }
+    }
+
+    // four segments sealed with the credential, returns the path, its bytes, the header length and the length of a full segment on disk
+    fn segmented_file(name: &str, credential: &Credential) -> (PathBuf, Vec<u8>, usize, usize) {
+        let input = temp_path(&format!("{}.txt", name));
+        let encrypted = temp_path(&format!("{}.enc", name));
+        fs::write(&input, vec![7u8; 3 * stream::SEGMENT_LEN + 100]).unwrap();
+        encrypt_file(&input, &encrypted, std::slice::from_ref(credential), Algorithm::chacha20_poly1305(), Kdf::Scrypt { log_n: 10, r: 8, p: 1 }, EncryptOptions::default()).unwrap();
+        fs::remove_file(&input).unwrap();
+
+        let header = read_header(&encrypted);
+        let tag_len = Cipher::new(&header.algorithm, &[0u8; KEY_LEN]).tag_len();
+        (encrypted.clone(), fs::read(&encrypted).unwrap(), header.to_bytes().unwrap().len(), stream::SEGMENT_LEN + tag_len)
+    }
+
+    #[test]
+    fn dropping_the_final_segment_is_tampering() {
+        let credential = Credential::Passphrase { passphrase: "segments".to_string(), keyfile: None };
+        let (path, bytes, header_len, segment_len) = segmented_file("drop-last", &credential);
+        fs::write(&path, &bytes[..header_len + 3 * segment_len]).unwrap(); //ends on a full segment that was not sealed as the last one
+        assert!(matches!(decrypted_file(&path, &credential), Err(CryptoError::Tampered)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn swapping_segments_is_tampering() {
+        let credential = Credential::Passphrase { passphrase: "segments".to_string(), keyfile: None };
+        let (path, mut bytes, header_len, segment_len) = segmented_file("swap", &credential);
+        let (first, rest) = bytes[header_len..].split_at_mut(segment_len);
+        first.swap_with_slice(&mut rest[..segment_len]);
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(decrypted_file(&path, &credential), Err(CryptoError::Tampered)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn clearing_the_last_flag_is_tampering() {
+        let credential = Credential::Passphrase { passphrase: "segments".to_string(), keyfile: None };
+        let (path, mut bytes, header_len, segment_len) = segmented_file("last-flag", &credential);
+        let header = read_header(&path);
+        let cipher = Cipher::new(&header.algorithm, &unlock(&header, &credential).unwrap());
+        let aad = header.associated_data().unwrap();
+        let nonce = |last: bool| [header.nonce.as_slice(), &3u32.to_be_bytes(), &[last as u8]].concat();
+
+        let start = header_len + 3 * segment_len; //resealed with the real key so only the flag differs
+        let plaintext = cipher.decrypt(&nonce(true), &aad, &bytes[start..]).unwrap();
+        bytes.truncate(start);
+        bytes.extend_from_slice(&cipher.encrypt(&nonce(false), &aad, &plaintext).unwrap());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(decrypted_file(&path, &credential), Err(CryptoError::Tampered)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewriting_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        let old = Credential::Passphrase { passphrase: "old".to_string(), keyfile: None };
+        let new = Credential::Passphrase { passphrase: "new".to_string(), keyfile: None };
+        let input = temp_path("mode.txt");
+        let encrypted = temp_path("mode.enc");
+        fs::write(&input, b"private").unwrap();
+
+        encrypt_file(&input, &encrypted, std::slice::from_ref(&old), Algorithm::chacha20_poly1305(), kdf.clone(), EncryptOptions::default()).unwrap();
+        assert_eq!(fs::metadata(&encrypted).unwrap().permissions().mode() & 0o777, 0o600); //new files start out private
+        fs::set_permissions(&encrypted, fs::Permissions::from_mode(0o640)).unwrap();
+        change_credentials(&encrypted, &old, std::slice::from_ref(&old), std::slice::from_ref(&new), None).unwrap();
+        assert!(matches!(key_slots(&read_header(&encrypted)).as_slice(), [KeySlot::Passphrase { kdf: slot_kdf, .. }] if *slot_kdf == kdf)); //kept, not reset to the default
+        assert_eq!(fs::metadata(&encrypted).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(decrypted_file(&encrypted, &new).unwrap(), b"private");
+
+        fs::set_permissions(&input, fs::Permissions::from_mode(0o644)).unwrap();
+        encrypt_file(&input, &encrypted, std::slice::from_ref(&new), Algorithm::chacha20_poly1305(), kdf.clone(), EncryptOptions::default()).unwrap();
+        fs::set_permissions(&encrypted, fs::Permissions::from_mode(0o600)).unwrap();
+        create_decrypted_file(&encrypted, &encrypted, &new).unwrap(); //in place, the sealed mode has to win over the encrypted file's
+        assert_eq!(fs::read(&encrypted).unwrap(), b"private");
+        assert_eq!(fs::metadata(&encrypted).unwrap().permissions().mode() & 0o777, 0o644);
+
+        fs::set_permissions(&input, fs::Permissions::from_mode(0o4755)).unwrap();
+        encrypt_file(&input, &encrypted, std::slice::from_ref(&new), Algorithm::chacha20_poly1305(), kdf.clone(), EncryptOptions::default()).unwrap();
+        let decrypted = temp_path("mode-setuid.txt");
+        create_decrypted_file(&encrypted, &decrypted, &new).unwrap();
+        assert_eq!(fs::metadata(&decrypted).unwrap().permissions().mode() & 0o7777, 0o755); //setuid only comes back with the owner
+
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+        fs::remove_file(&decrypted).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extracting_keeps_directory_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let kdf = Kdf::Scrypt { log_n: 10, r: 8, p: 1 };
+        let credential = Credential::Passphrase { passphrase: "tree".to_string(), keyfile: None };
+        let dir = temp_path("mode-dir");
+        let encrypted = temp_path("mode-dir.enc");
+        let extracted = temp_path("mode-dir.out");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("file"), b"inside").unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o750)).unwrap();
+
+        encrypt_directory(&dir, &encrypted, std::slice::from_ref(&credential), Algorithm::chacha20_poly1305(), kdf, EncryptOptions::default()).unwrap();
+        extract_directory(&encrypted, &extracted, &credential).unwrap();
+        assert_eq!(fs::metadata(&extracted).unwrap().permissions().mode() & 0o777, 0o750);
+        assert_eq!(fs::read(extracted.join("file")).unwrap(), b"inside");
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&extracted).unwrap();
+        fs::remove_file(&encrypted).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::io::{self, BufRead, Read, Write};
+
+use super::cipher::{Algorithm, Cipher};
+use super::stream::{StreamReader, StreamWriter};
+use super::{Credential, CredentialKind, CryptoError, Identity, Kdf, Recipient, KEY_LEN};
+
+pub const VERSION_LINE: &str = "age-encryption.org/v1";
+const FILE_KEY_LEN: usize = 16;
+const WRAPPED_LEN: usize = FILE_KEY_LEN + 16;
+const PAYLOAD_NONCE_LEN: usize = 16;
+const STREAM_PREFIX: [u8; 7] = [0; 7]; //age counts chunks with an 11-byte counter, which is our 4-byte counter behind a zero prefix
+const SCRYPT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+const X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+const SCRYPT_SALT_LEN: usize = 16;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const DEFAULT_LOG_N: u32 = 18; //what age itself uses
+const COLUMNS: usize = 64;
+const MAX_LINE_LEN: u64 = 4096;
+
+// the age v1 format (https://age-encryption.org/v1), readable and writable next to our own:
+// version line, one "-> type args" stanza per recipient with a wrapped 16-byte file key as base64 body,
+// "--- " and an HMAC of the header, then a 16-byte nonce and the payload as ChaCha20-Poly1305 STREAM
+struct Stanza {
+    kind: String,
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+pub struct Header {
+    stanzas: Vec<Stanza>,
+    mac: Vec<u8>,
+    text: Vec<u8>, //everything the MAC covers, up to and including "---"
+}
+
+fn invalid(message: &str) -> CryptoError {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid age file: {}", message)).into()
+}
+
+fn unsupported(message: &str) -> CryptoError {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string()).into()
+}
+
+fn hkdf(salt: &[u8], file_key: &[u8], info: &[u8]) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(salt), file_key).expand(info, &mut key).expect("32 bytes is a valid HKDF length");
+    key
+}
+
+fn wrap(wrapping_key: &[u8; KEY_LEN], file_key: &[u8; FILE_KEY_LEN]) -> Vec<u8> {
+    ChaCha20Poly1305::new(wrapping_key.into())
+        .encrypt(&[0u8; 12].into(), file_key.as_slice())
+        .expect("sealing a 16-byte key cannot fail")
+}
+
+fn unwrap(wrapping_key: &[u8; KEY_LEN], body: &[u8]) -> Option<[u8; FILE_KEY_LEN]> {
+    let file_key = ChaCha20Poly1305::new(wrapping_key.into()).decrypt(&[0u8; 12].into(), body).ok()?;
+    file_key.try_into().ok()
+}
+
+fn header_mac(file_key: &[u8; FILE_KEY_LEN], text: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&hkdf(&[], file_key, b"header")).expect("HMAC accepts any key length");
+    mac.update(text);
+    mac
+}
+
+fn decode(text: &str, len: usize) -> Result<Vec<u8>, CryptoError> { //age only accepts canonical unpadded base64
+    STANDARD_NO_PAD.decode(text).ok().filter(|bytes| bytes.len() == len).ok_or_else(|| invalid("bad base64"))
+}
+
+fn read_line<R: BufRead>(input: &mut R, text: &mut Vec<u8>) -> Result<String, CryptoError> { //one newline terminated line, also kept in text for the MAC
+    let mut line = Vec::new();
+    input.take(MAX_LINE_LEN).read_until(b'\n', &mut line)?;
+    if line.pop() != Some(b'\n') {
+        return Err(invalid("header is truncated"));
+    }
+    text.extend_from_slice(&line);
+    text.push(b'\n');
+    String::from_utf8(line).map_err(|_| invalid("header is not text"))
+}
+
+fn write_stanza(text: &mut String, kind: &str, args: &[String], body: &[u8]) {
+    text.push_str("-> ");
+    text.push_str(kind);
+    for arg in args {
+        text.push(' ');
+        text.push_str(arg);
+    }
+    text.push('\n');
+    let encoded = STANDARD_NO_PAD.encode(body);
+    for line in encoded.as_bytes().chunks(COLUMNS) {
+        text.push_str(std::str::from_utf8(line).expect("base64 is ascii"));
+        text.push('\n');
+    }
+    if encoded.len().is_multiple_of(COLUMNS) { //a full last line has to be followed by an empty one
+        text.push('\n');
+    }
+}
+
+fn scrypt_kdf(log_n: u32) -> Kdf {
+    Kdf::Scrypt { log_n, r: SCRYPT_R, p: SCRYPT_P }
+}
+
+fn scrypt_salt(salt: &[u8]) -> Vec<u8> {
+    [SCRYPT_LABEL, salt].concat()
+}
+
+fn x25519_wrapping_key(shared: &[u8; 32], share: &[u8; 32], recipient: &Recipient) -> [u8; KEY_LEN] {
+    hkdf(&[share.as_slice(), &recipient.to_bytes()].concat(), shared, X25519_LABEL)
+}
+
+fn write_x25519_stanza(text: &mut String, recipient: &Recipient, file_key: &[u8; FILE_KEY_LEN]) -> Result<(), CryptoError> {
+    let ephemeral = Identity::generate();
+    let share = ephemeral.to_recipient().to_bytes();
+    let shared = ephemeral.diffie_hellman(&recipient.to_bytes()).ok_or_else(|| unsupported("Invalid public key"))?;
+    write_stanza(text, "X25519", &[STANDARD_NO_PAD.encode(share)], &wrap(&x25519_wrapping_key(&shared, &share, recipient), file_key));
+    Ok(())
+}
+
+impl Header {
+    pub fn read_from<R: BufRead>(input: &mut R) -> Result<Self, CryptoError> {
+        let mut text = Vec::new();
+        if read_line(input, &mut text)? != VERSION_LINE {
+            return Err(invalid("unsupported version"));
+        }
+
+        let mut stanzas = Vec::new();
+        loop {
+            let start = text.len();
+            let line = read_line(input, &mut text)?;
+            if let Some(mac) = line.strip_prefix("--- ") {
+                text.truncate(start + 3);
+                return Ok(Header { stanzas, mac: decode(mac, 32)?, text });
+            }
+
+            let mut args = line.strip_prefix("-> ").ok_or_else(|| invalid("expected a stanza"))?.split(' ').map(str::to_string);
+            let kind = args.next().unwrap_or_default();
+            let args: Vec<String> = args.collect();
+            if kind.is_empty() || args.iter().any(String::is_empty) {
+                return Err(invalid("empty stanza argument"));
+            }
+
+            let mut body = Vec::new();
+            loop { //base64 wrapped at 64 columns, ending with the first shorter line
+                let line = read_line(input, &mut text)?;
+                if line.len() > COLUMNS {
+                    return Err(invalid("stanza line is too long"));
+                }
+                body.extend(STANDARD_NO_PAD.decode(&line).map_err(|_| invalid("bad base64"))?);
+                if line.len() < COLUMNS {
+                    break;
+                }
+            }
+            stanzas.push(Stanza { kind, args, body });
+        }
+    }
+
+    pub fn encoded_len(&self) -> u64 { //the text plus " " base64 MAC and newline, where the payload starts
+        (self.text.len() + 1 + STANDARD_NO_PAD.encode(&self.mac).len() + 1) as u64
+    }
+
+    pub fn credential_kinds(&self) -> Vec<CredentialKind> {
+        let mut kinds = Vec::new();
+        for stanza in &self.stanzas {
+            let kind = match stanza.kind.as_str() {
+                "scrypt" => CredentialKind::Passphrase { keyfile: false },
+                "X25519" => CredentialKind::Identity,
+                _ => continue, //stanzas for plugins and other recipient types we can't open
+            };
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+
+    fn open_scrypt(&self, stanza: &Stanza, passphrase: &[u8]) -> Result<Option<[u8; FILE_KEY_LEN]>, CryptoError> {
+        if self.stanzas.len() != 1 { //otherwise a passphrase could be mixed with recipients that don't know it
+            return Err(invalid("an scrypt stanza must be the only one"));
+        }
+        let [salt, log_n] = stanza.args.as_slice() else { return Err(invalid("bad scrypt stanza")) };
+        let salt = decode(salt, SCRYPT_SALT_LEN)?;
+        let log_n = log_n.parse::<u32>().ok().filter(|n| *n > 0 && n.to_string() == *log_n).ok_or_else(|| invalid("bad scrypt work factor"))?;
+        if stanza.body.len() != WRAPPED_LEN {
+            return Err(invalid("bad scrypt stanza"));
+        }
+        let kdf = scrypt_kdf(log_n);
+        if !kdf.is_sane() {
+            return Err(CryptoError::Kdf(format!("scrypt work factor {} is too expensive", log_n)));
+        }
+        Ok(unwrap(&kdf.derive_key(passphrase, &scrypt_salt(&salt))?, &stanza.body))
+    }
+
+    fn open_x25519(stanza: &Stanza, identity: &Identity) -> Result<Option<[u8; FILE_KEY_LEN]>, CryptoError> {
+        let [share] = stanza.args.as_slice() else { return Err(invalid("bad X25519 stanza")) };
+        let share: [u8; 32] = decode(share, 32)?.try_into().expect("length was checked");
+        if stanza.body.len() != WRAPPED_LEN {
+            return Err(invalid("bad X25519 stanza"));
+        }
+        let shared = identity.diffie_hellman(&share).ok_or_else(|| invalid("low order X25519 share"))?;
+        Ok(unwrap(&x25519_wrapping_key(&shared, &share, &identity.to_recipient()), &stanza.body))
+    }
+
+    fn file_key(&self, credential: &Credential) -> Result<[u8; FILE_KEY_LEN], CryptoError> { //try every stanza the credential could open
+        let mut found = false;
+        for stanza in &self.stanzas {
+            let file_key = match (stanza.kind.as_str(), credential) {
+                ("scrypt", Credential::Passphrase { .. }) => self.open_scrypt(stanza, &credential.secret(false)?)?, //age has no keyfiles
+                ("X25519", Credential::Identity(identity)) => Self::open_x25519(stanza, identity)?,
+                _ => continue,
+            };
+            match file_key {
+                Some(file_key) => return Ok(file_key),
+                None => found = true,
+            }
+        }
+        if found {
+            Err(CryptoError::WrongKey)
+        } else if self.credential_kinds().contains(&CredentialKind::Identity) {
+            Err(CryptoError::IdentityRequired)
+        } else {
+            Err(CryptoError::PassphraseRequired)
+        }
+    }
+}
+
+pub fn encrypt<R: Read, W: Write>(mut input: R, mut output: W, credentials: &[Credential], kdf: &Kdf) -> Result<(), CryptoError> {
+    let mut file_key = [0u8; FILE_KEY_LEN];
+    OsRng.fill_bytes(&mut file_key);
+
+    let mut text = format!("{}\n", VERSION_LINE);
+    for credential in credentials {
+        match credential {
+            Credential::Passphrase { keyfile: Some(_), .. } => return Err(unsupported("age files can't be locked with a keyfile")),
+            Credential::Passphrase { .. } if credentials.len() > 1 => return Err(unsupported("An age file with a passphrase can't have other recipients")),
+            Credential::Passphrase { .. } => {
+                let log_n = match kdf {
+                    Kdf::Scrypt { log_n, .. } => *log_n,
+                    _ => DEFAULT_LOG_N, //age only knows scrypt
+                };
+                let mut salt = [0u8; SCRYPT_SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                let wrapping_key = scrypt_kdf(log_n).derive_key(&credential.secret(false)?, &scrypt_salt(&salt))?;
+                write_stanza(&mut text, "scrypt", &[STANDARD_NO_PAD.encode(salt), log_n.to_string()], &wrap(&wrapping_key, &file_key));
+            }
+            Credential::Recipient(recipient) => write_x25519_stanza(&mut text, recipient, &file_key)?,
+            Credential::Identity(identity) => write_x25519_stanza(&mut text, &identity.to_recipient(), &file_key)?, //encrypting to yourself
+            Credential::Key(_) => return Err(unsupported("age files can't be locked with a raw key")),
+        }
+    }
+    text.push_str("---");
+    let mac = header_mac(&file_key, text.as_bytes()).finalize().into_bytes();
+    output.write_all(text.as_bytes())?;
+    output.write_all(format!(" {}\n", STANDARD_NO_PAD.encode(mac)).as_bytes())?;
+
+    let mut nonce = [0u8; PAYLOAD_NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    output.write_all(&nonce)?;
+    let payload_key = hkdf(&nonce, &file_key, b"payload");
+    let mut writer = StreamWriter::new(output, Cipher::new(&Algorithm::chacha20_poly1305(), &payload_key), &STREAM_PREFIX, &[]);
+    io::copy(&mut input, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn decrypt<R: Read, W: Write>(header: &Header, mut input: R, credential: &Credential, output: &mut W) -> Result<(), CryptoError> {
+    let file_key = header.file_key(credential)?;
+    if header_mac(&file_key, &header.text).verify_slice(&header.mac).is_err() {
+        return Err(CryptoError::Tampered);
+    }
+
+    let mut nonce = [0u8; PAYLOAD_NONCE_LEN];
+    input.read_exact(&mut nonce).map_err(|_| CryptoError::Tampered)?;
+    let payload_key = hkdf(&nonce, &file_key, b"payload");
+    let mut reader = StreamReader::new(input, Cipher::new(&Algorithm::chacha20_poly1305(), &payload_key), &STREAM_PREFIX, &[]);
+    match io::copy(&mut reader, output) {
+        Ok(_) => Ok(()),
+        Err(e) => match CryptoError::from(e) { //the header MAC proved the key right, so a failing chunk means the file was modified
+            CryptoError::Aead(_) => Err(CryptoError::Tampered),
+            CryptoError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(CryptoError::Tampered),
+            e => Err(e),
+        },
+    }
+}
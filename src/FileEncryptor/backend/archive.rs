@@ -0,0 +1,55 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+
+// a whole directory travels as a tar stream inside the sealed payload, marked by FLAG_ARCHIVE in the header.
+// Paths are relative to the directory, symlinks are stored as links rather than followed
+pub fn pack<W: Write>(dir: &Path, output: W) -> io::Result<W> {
+    let mut builder = tar::Builder::new(output);
+    builder.follow_symlinks(false); //a link out of the directory must not pull in what it points to
+    builder.append_dir_all(".", dir)?;
+    builder.into_inner()
+}
+
+pub fn unpack<R: Read>(input: R, dir: &Path, keep_owner: bool) -> io::Result<()> { //dir must exist and be empty
+    let mut archive = tar::Archive::new(input);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_ownerships(keep_owner);
+    archive.set_overwrite(false);
+    let mut root_mode = None;
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.components().all(|component| component == Component::CurDir) { //tar leaves dir itself alone, it was created private
+            root_mode = Some(entry.header().mode()?);
+        } else if entry.header().entry_type().is_dir() {
+            directories.push(entry); //after their contents, like Archive::unpack, so read-only ones can still be filled
+        } else {
+            entry.unpack_in(dir)?; //refuses entries that would land outside dir, through `..` or a symlink
+        }
+    }
+    for mut directory in directories.into_iter().rev() {
+        directory.unpack_in(dir)?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = root_mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = root_mode;
+    io::copy(&mut archive.into_inner(), &mut io::sink())?; //read to the end so a truncated payload is still noticed
+    Ok(())
+}
+
+pub fn check<R: Read>(input: R) -> io::Result<(usize, u64)> { //reads every entry without writing anything, returns how many there were and their total size
+    let mut archive = tar::Archive::new(input);
+    let (mut files, mut bytes) = (0, 0);
+    for entry in archive.entries()? {
+        bytes += io::copy(&mut entry?, &mut io::sink())?;
+        files += 1;
+    }
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok((files, bytes))
+}
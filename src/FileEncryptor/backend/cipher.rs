@@ -0,0 +1,147 @@
+use aes_gcm::aead::generic_array::typenum::Unsigned;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, KeySizeUser, Payload};
+use aes_gcm::Aes256Gcm;
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+
+use super::stream::NONCE_SUFFIX_LEN;
+use super::CryptoError;
+
+pub trait AeadSuite: Sync { //everything the backend needs to know about an AEAD to use it for files
+    fn name(&self) -> &'static str;
+    fn id(&self) -> u8; //stored in the header, must never change once files exist
+    fn key_len(&self) -> usize;
+    fn nonce_len(&self) -> usize;
+    fn tag_len(&self) -> usize;
+    fn seal(&self, key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError>;
+    fn open(&self, key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError>;
+}
+
+pub struct RustCryptoSuite<A> { //any RustCrypto AEAD, with sizes taken from the type itself
+    name: &'static str,
+    id: u8,
+    aead: PhantomData<fn() -> A>,
+}
+
+impl<A> RustCryptoSuite<A> {
+    pub const fn new(name: &'static str, id: u8) -> Self {
+        Self { name, id, aead: PhantomData }
+    }
+}
+
+impl<A: KeyInit + Aead> RustCryptoSuite<A> {
+    fn keyed(&self, key: &[u8], nonce: &[u8]) -> Result<A, CryptoError> {
+        if nonce.len() != self.nonce_len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} needs a {}-byte nonce", self.name, self.nonce_len())).into());
+        }
+        A::new_from_slice(key).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} needs a {}-byte key", self.name, self.key_len())).into())
+    }
+}
+
+impl<A: KeyInit + Aead> AeadSuite for RustCryptoSuite<A> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn key_len(&self) -> usize {
+        <A as KeySizeUser>::KeySize::USIZE
+    }
+
+    fn nonce_len(&self) -> usize {
+        <A as AeadCore>::NonceSize::USIZE
+    }
+
+    fn tag_len(&self) -> usize {
+        <A as AeadCore>::TagSize::USIZE
+    }
+
+    fn seal(&self, key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.keyed(key, nonce)?.encrypt(GenericArray::from_slice(nonce), Payload { msg: plaintext, aad })?)
+    }
+
+    fn open(&self, key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        Ok(self.keyed(key, nonce)?.decrypt(GenericArray::from_slice(nonce), Payload { msg: ciphertext, aad })?)
+    }
+}
+
+const AES_GCM: RustCryptoSuite<Aes256Gcm> = RustCryptoSuite::new("AES-256-GCM", 1);
+const AES_GCM_SIV: RustCryptoSuite<Aes256GcmSiv> = RustCryptoSuite::new("AES-256-GCM-SIV", 2);
+const CHACHA20_POLY1305: RustCryptoSuite<ChaCha20Poly1305> = RustCryptoSuite::new("ChaCha20-Poly1305", 3);
+const XCHACHA20_POLY1305: RustCryptoSuite<XChaCha20Poly1305> = RustCryptoSuite::new("XChaCha20-Poly1305", 4);
+
+// every algorithm that can be picked in the GUI or read from a header, in display order
+static REGISTRY: &[&dyn AeadSuite] = &[&AES_GCM, &AES_GCM_SIV, &CHACHA20_POLY1305, &XCHACHA20_POLY1305];
+
+#[derive(Clone, Copy)]
+pub struct Algorithm(&'static dyn AeadSuite);
+
+impl Algorithm {
+    pub fn all() -> impl Iterator<Item = Algorithm> {
+        REGISTRY.iter().map(|suite| Algorithm(*suite))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    pub(super) fn identifier(&self) -> u8 {
+        self.0.id()
+    }
+
+    pub(super) fn from_identifier(id: u8) -> Option<Self> {
+        Self::all().find(|algorithm| algorithm.identifier() == id)
+    }
+
+    pub(super) fn chacha20_poly1305() -> Self { //the only AEAD age uses
+        Algorithm(&CHACHA20_POLY1305)
+    }
+
+    pub(super) fn nonce_prefix_len(&self) -> usize { //the rest of the nonce is the STREAM counter and last segment flag
+        self.0.nonce_len() - NONCE_SUFFIX_LEN
+    }
+}
+
+impl PartialEq for Algorithm {
+    fn eq(&self, other: &Self) -> bool {
+        self.identifier() == other.identifier()
+    }
+}
+
+impl Eq for Algorithm {}
+
+impl fmt::Debug for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub struct Cipher { //an algorithm keyed and ready to seal or open
+    suite: &'static dyn AeadSuite,
+    key: Vec<u8>,
+}
+
+impl Cipher {
+    pub(super) fn new(algorithm: &Algorithm, key: &[u8]) -> Self {
+        Self { suite: algorithm.0, key: key.to_vec() }
+    }
+
+    pub(super) fn tag_len(&self) -> usize {
+        self.suite.tag_len()
+    }
+
+    pub(super) fn encrypt(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.suite.seal(&self.key, nonce, aad, plaintext)
+    }
+
+    pub(super) fn decrypt(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.suite.open(&self.key, nonce, aad, ciphertext)
+    }
+}
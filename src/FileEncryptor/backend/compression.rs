@@ -0,0 +1,99 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{self, Read, Write};
+
+use super::CryptoError;
+
+const ZSTD_LEVEL: i32 = 3;
+const DEFLATE_LEVEL: u32 = 6;
+
+// magic numbers of formats that are already compressed, squeezing them again only costs time
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    b"\x28\xb5\x2f\xfd", //zstd
+    b"\x1f\x8b", //gzip
+    b"PK\x03\x04", //zip, docx, xlsx, jar, apk
+    b"\xfd7zXZ\x00", //xz
+    b"BZh", //bzip2
+    b"7z\xbc\xaf\x27\x1c", //7z
+    b"Rar!\x1a\x07", //rar
+    b"\x04\x22\x4d\x18", //lz4
+    b"\x89PNG\r\n\x1a\n", //png
+    b"\xff\xd8\xff", //jpeg
+    b"GIF8", //gif
+    b"OggS", //ogg
+    b"fLaC", //flac
+    b"ID3", //mp3
+    b"\x1a\x45\xdf\xa3", //mkv, webm
+    b"FENCRYPT", //already encrypted, so it can't be compressed either
+    b"age-encryption.org/",
+    b"Salted__",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression { //runs before sealing, recorded in the header
+    Zstd { level: i32 },
+    Deflate { level: u32 },
+}
+
+impl Compression {
+    pub fn zstd() -> Self {
+        Compression::Zstd { level: ZSTD_LEVEL }
+    }
+
+    pub fn deflate() -> Self {
+        Compression::Deflate { level: DEFLATE_LEVEL }
+    }
+
+    // codec id u8 | level i32, stored as a critical header extension
+    pub(super) fn to_bytes(self) -> Vec<u8> {
+        let (id, level) = match self {
+            Compression::Zstd { level } => (1, level),
+            Compression::Deflate { level } => (2, level as i32),
+        };
+        let mut bytes = vec![id];
+        bytes.extend_from_slice(&level.to_le_bytes());
+        bytes
+    }
+
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
+        let unsupported = || io::Error::new(io::ErrorKind::InvalidData, "Unsupported compression in header");
+        let (&id, level) = bytes.split_first().ok_or_else(unsupported)?;
+        let level = i32::from_le_bytes(level.try_into().map_err(|_| unsupported())?);
+        match id {
+            1 => Ok(Compression::Zstd { level }),
+            2 if (0..=9).contains(&level) => Ok(Compression::Deflate { level: level as u32 }),
+            _ => Err(unsupported().into()),
+        }
+    }
+
+    pub(super) fn compress<W: Write, F>(&self, output: W, content: F) -> io::Result<W>
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()>,
+    { //content writes into the encoder, the output is handed back so the caller can finish it
+        match self {
+            Compression::Zstd { level } => {
+                let mut encoder = zstd::Encoder::new(output, *level)?;
+                content(&mut encoder)?;
+                encoder.finish()
+            }
+            Compression::Deflate { level } => {
+                let mut encoder = DeflateEncoder::new(output, flate2::Compression::new(*level));
+                content(&mut encoder)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    pub(super) fn decompressor<'a, R: Read + 'a>(&self, input: R) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Compression::Zstd { .. } => Ok(Box::new(zstd::Decoder::new(input)?)),
+            Compression::Deflate { .. } => Ok(Box::new(DeflateDecoder::new(input))),
+        }
+    }
+}
+
+pub fn looks_compressed(prefix: &[u8]) -> bool {
+    COMPRESSED_MAGIC.iter().any(|magic| prefix.starts_with(magic))
+        || prefix.get(4..8).is_some_and(|brand| brand == b"ftyp") //mp4, mov, heic
+        || (prefix.starts_with(b"RIFF") && prefix.get(8..12).is_some_and(|kind| kind == b"WEBP"))
+}
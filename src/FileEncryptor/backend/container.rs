@@ -0,0 +1,202 @@
+use aes_gcm::aead::OsRng;
+use rand::RngCore;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::{Algorithm, Cipher, CryptoError, KEY_LEN};
+use super::stream::{StreamReader, StreamWriter};
+
+const INDEX_LEN_LEN: u64 = 8;
+const MAX_INDEX_LEN: u64 = 64 * 1024 * 1024;
+
+// FLAG_CONTAINER files: header | entries | index nonce prefix | sealed index | index len u64, rewritten whole on every change
+// every entry is its own STREAM under a random nonce prefix, so one can be read without touching the others.
+// The index lists name, size, where the entry starts relative to the end of the header, its length and nonce prefix,
+// and is sealed too, so not even the names are visible without a key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    offset: u64,
+    len: u64,
+    nonce: Vec<u8>,
+}
+
+fn invalid(message: &str) -> CryptoError {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string()).into()
+}
+
+fn index_to_bytes(entries: &[Entry]) -> Vec<u8> { //count u32, then name len u16 | name | size u64 | offset u64 | len u64 | nonce len u8 | nonce
+    let mut bytes = (entries.len() as u32).to_le_bytes().to_vec();
+    for entry in entries {
+        bytes.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(entry.name.as_bytes());
+        bytes.extend_from_slice(&entry.size.to_le_bytes());
+        bytes.extend_from_slice(&entry.offset.to_le_bytes());
+        bytes.extend_from_slice(&entry.len.to_le_bytes());
+        bytes.push(entry.nonce.len() as u8);
+        bytes.extend_from_slice(&entry.nonce);
+    }
+    bytes
+}
+
+fn index_from_bytes(mut bytes: &[u8]) -> Result<Vec<Entry>, CryptoError> {
+    let malformed = || invalid("Archive index is malformed");
+    let mut take = |len: usize| -> Result<&[u8], CryptoError> {
+        if bytes.len() < len {
+            return Err(malformed());
+        }
+        let (head, tail) = bytes.split_at(len);
+        bytes = tail;
+        Ok(head)
+    };
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let name_len = u16::from_le_bytes(take(2)?.try_into().unwrap()) as usize;
+        let name = String::from_utf8(take(name_len)?.to_vec()).map_err(|_| malformed())?;
+        let size = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let offset = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let len = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        let nonce_len = take(1)?[0] as usize;
+        let nonce = take(nonce_len)?.to_vec();
+        entries.push(Entry { name, size, offset, len, nonce });
+    }
+    if !bytes.is_empty() {
+        return Err(malformed());
+    }
+    Ok(entries)
+}
+
+fn index_aad(aad: &[u8]) -> Vec<u8> { //keeps an entry from passing for the index or the other way round
+    [aad, b"index"].concat()
+}
+
+fn random_nonce(algorithm: &Algorithm) -> Vec<u8> {
+    let mut nonce = vec![0u8; algorithm.nonce_prefix_len()];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+fn write_index<W: Write + Seek>(output: &mut W, algorithm: &Algorithm, key: &[u8], aad: &[u8], entries: &[Entry]) -> Result<(), CryptoError> { //nonce, sealed index and its length from where output is
+    let start = output.stream_position()?;
+    let nonce = random_nonce(algorithm);
+    output.write_all(&nonce)?;
+    let mut writer = StreamWriter::new(&mut *output, Cipher::new(algorithm, key), &nonce, aad);
+    writer.write_all(&index_to_bytes(entries))?;
+    writer.finish()?;
+    let end = output.stream_position()?;
+    output.write_all(&(end - start).to_le_bytes())?;
+    Ok(())
+}
+
+pub struct Container {
+    file: File,
+    algorithm: Algorithm,
+    key: [u8; KEY_LEN],
+    aad: Vec<u8>,
+    body_start: u64, //where the header ends, offsets count from here so the key slots can change size
+    pub entries: Vec<Entry>,
+}
+
+impl Container {
+    pub fn create<W: Write + Seek>(output: &mut W, algorithm: Algorithm, key: [u8; KEY_LEN], aad: &[u8]) -> Result<(), CryptoError> { //the header has already been written
+        write_index(output, &algorithm, &key, &index_aad(aad), &[])
+    }
+
+    pub fn open(mut file: File, algorithm: Algorithm, key: [u8; KEY_LEN], aad: &[u8]) -> Result<Self, CryptoError> { //the file is positioned just after the header
+        let body_start = file.stream_position()?;
+        let end = file.seek(SeekFrom::End(0))?;
+        if end < body_start + INDEX_LEN_LEN {
+            return Err(invalid("Archive is truncated"));
+        }
+        file.seek(SeekFrom::Start(end - INDEX_LEN_LEN))?;
+        let mut len = [0u8; INDEX_LEN_LEN as usize];
+        file.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        if len > MAX_INDEX_LEN || len > end - INDEX_LEN_LEN - body_start || len < algorithm.nonce_prefix_len() as u64 {
+            return Err(invalid("Archive index is malformed"));
+        }
+
+        file.seek(SeekFrom::Start(end - INDEX_LEN_LEN - len))?;
+        let mut nonce = vec![0u8; algorithm.nonce_prefix_len()];
+        file.read_exact(&mut nonce)?;
+        let mut index = Vec::new();
+        let sealed = (&mut file).take(len - nonce.len() as u64);
+        StreamReader::new(sealed, Cipher::new(&algorithm, &key), &nonce, &index_aad(aad)).read_to_end(&mut index)?;
+        let entries = index_from_bytes(&index)?;
+        Ok(Self { file, algorithm, key, aad: aad.to_vec(), body_start, entries })
+    }
+
+    fn entry_aad(&self) -> Vec<u8> {
+        [self.aad.as_slice(), b"entry"].concat()
+    }
+
+    pub fn entry(&self, name: &str) -> Result<&Entry, CryptoError> {
+        self.entries.iter().find(|entry| entry.name == name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{:?} is not in the archive", name)).into())
+    }
+
+    pub fn extract<W: Write>(&mut self, name: &str, output: &mut W) -> Result<(), CryptoError> { //only this entry is read and decrypted
+        let entry = self.entry(name)?.clone();
+        self.file.seek(SeekFrom::Start(self.body_start + entry.offset))?;
+        let aad = self.entry_aad();
+        let sealed = (&mut self.file).take(entry.len);
+        let mut reader = StreamReader::new(sealed, Cipher::new(&self.algorithm, &self.key), &entry.nonce, &aad);
+        if io::copy(&mut reader, output)? != entry.size {
+            return Err(CryptoError::Tampered);
+        }
+        Ok(())
+    }
+
+    // writes the header and every entry but the skipped one, still sealed, into output, which then holds a single index
+    // once write_index is called. Rewriting the whole archive means no older index is ever left behind in the file
+    // that cutting it short could bring back, together with entries that were deleted since
+    fn copy_entries<W: Write>(&mut self, header: &[u8], skip: Option<&str>, output: &mut W) -> Result<u64, CryptoError> { //returns where the next entry goes
+        output.write_all(header)?;
+        let mut offset = 0;
+        for mut entry in std::mem::take(&mut self.entries) {
+            if Some(entry.name.as_str()) == skip {
+                continue;
+            }
+            self.file.seek(SeekFrom::Start(self.body_start + entry.offset))?;
+            if io::copy(&mut (&mut self.file).take(entry.len), output)? != entry.len {
+                return Err(invalid("Archive is truncated"));
+            }
+            entry.offset = offset;
+            offset += entry.len;
+            self.entries.push(entry);
+        }
+        Ok(offset)
+    }
+
+    fn write_index<W: Write + Seek>(&self, output: &mut W) -> Result<(), CryptoError> {
+        write_index(output, &self.algorithm, &self.key, &index_aad(&self.aad), &self.entries)
+    }
+
+    // copies the other entries into output, seals the new one after them and writes the index at the end
+    pub fn add<R: Read, W: Write + Seek>(mut self, name: &str, input: &mut R, header: &[u8], output: &mut W) -> Result<(), CryptoError> {
+        if self.entries.iter().any(|entry| entry.name == name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} is already in the archive", name)).into());
+        }
+        if name.len() > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Name is too long").into());
+        }
+        let offset = self.copy_entries(header, None, output)?;
+        let nonce = random_nonce(&self.algorithm);
+        let start = output.stream_position()?;
+        let mut writer = StreamWriter::new(&mut *output, Cipher::new(&self.algorithm, &self.key), &nonce, &self.entry_aad());
+        let size = io::copy(input, &mut writer)?;
+        writer.finish()?;
+        let len = output.stream_position()? - start;
+        self.entries.push(Entry { name: name.to_string(), size, offset, len, nonce });
+        self.write_index(output)
+    }
+
+    // drops the entry from the index and copies the others, still sealed, into output so the space is given back
+    pub fn remove<W: Write + Seek>(mut self, name: &str, header: &[u8], output: &mut W) -> Result<(), CryptoError> {
+        self.entry(name)?;
+        self.copy_entries(header, Some(name), output)?;
+        self.write_index(output)
+    }
+}
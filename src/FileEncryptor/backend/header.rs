@@ -0,0 +1,166 @@
+use std::io::{self, Read};
+
+use super::{Algorithm, CryptoError};
+
+pub const MAGIC: [u8; 8] = *b"FENCRYPT";
+pub const FORMAT_VERSION: u8 = 1;
+pub const EXT_KEY_CHECK: u16 = 0x0001;
+pub const EXT_KEY_SLOT: u16 = 0x8001; //one wrapped copy of the file key, a file may have several
+pub const EXT_SLOT_MAC: u16 = 0x0002; //authenticates the key slots, which are left out of the associated data
+pub const EXT_SIGNATURE: u16 = 0x0003; //Ed25519 signature over the rest of the file, see signing.rs
+pub const EXT_COMPRESSION: u16 = 0x8002; //the payload was compressed before sealing, see compression.rs
+pub const EXT_PADDING: u16 = 0x8003; //the payload ends with length-hiding padding, see padding.rs
+pub const FLAG_METADATA: u32 = 0x0002; //the payload starts with the sealed file metadata, see metadata.rs
+pub const FLAG_ARCHIVE: u32 = 0x0004; //the payload is a whole directory packed as tar, see archive.rs
+pub const FLAG_CONTAINER: u32 = 0x0008; //separately sealed entries with an index instead of one payload, see container.rs
+pub const FLAG_MIRROR: u32 = 0x0010; //no payload, only the key slots of a mirrored folder, see mirror.rs
+
+const MAX_FIELD_LEN: usize = 255;
+const MAX_EXTENSIONS_LEN: usize = 1 << 20;
+const KNOWN_FLAGS: u32 = FLAG_METADATA | FLAG_ARCHIVE | FLAG_CONTAINER | FLAG_MIRROR;
+const CRITICAL_EXTENSION: u16 = 0x8000; //readers must refuse a file with a critical extension they don't understand
+const KNOWN_CRITICAL: &[u16] = &[EXT_KEY_SLOT, EXT_COMPRESSION, EXT_PADDING];
+
+// layout (integers are little-endian), every segment gets all of the header as associated data except the key slots,
+// their MAC and the signature, so those can be rewritten without touching the payload:
+// magic[8] | version u8 | algorithm u8 | nonce len u8 | nonce | flags u32 | extensions len u32 | extensions
+// (KDFs and salts are per key slot)
+// each extension is kind u16 | len u32 | data
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub nonce: Vec<u8>,
+    pub flags: u32,
+    pub extensions: Vec<Extension>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub kind: u16,
+    pub data: Vec<u8>,
+}
+
+fn invalid(message: &str) -> CryptoError {
+    CryptoError::Io(io::Error::new(io::ErrorKind::InvalidData, message.to_string()))
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+impl Header {
+    pub fn new(algorithm: Algorithm, nonce: &[u8]) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            algorithm,
+            nonce: nonce.to_vec(),
+            flags: 0,
+            extensions: Vec::new(),
+        }
+    }
+
+    pub fn extension(&self, kind: u16) -> Option<&[u8]> {
+        self.extensions.iter().find(|e| e.kind == kind).map(|e| e.data.as_slice())
+    }
+
+    pub fn extensions_of(&self, kind: u16) -> impl Iterator<Item = &[u8]> {
+        self.extensions.iter().filter(move |e| e.kind == kind).map(|e| e.data.as_slice())
+    }
+
+    pub fn add_extension(&mut self, kind: u16, data: Vec<u8>) {
+        self.extensions.push(Extension { kind, data });
+    }
+
+    pub fn set_extension(&mut self, kind: u16, data: Vec<u8>) {
+        self.extensions.retain(|e| e.kind != kind);
+        self.extensions.push(Extension { kind, data });
+    }
+
+    pub fn associated_data(&self) -> Result<Vec<u8>, CryptoError> {
+        let mut header = self.clone();
+        header.extensions.retain(|e| e.kind != EXT_KEY_SLOT && e.kind != EXT_SLOT_MAC && e.kind != EXT_SIGNATURE); //a signature is added after the payload is written
+        header.to_bytes()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CryptoError> {
+        if self.nonce.len() > MAX_FIELD_LEN {
+            return Err(invalid("Header field is too long"));
+        }
+
+        let mut extensions = Vec::new();
+        for extension in &self.extensions {
+            extensions.extend_from_slice(&extension.kind.to_le_bytes());
+            extensions.extend_from_slice(&(extension.data.len() as u32).to_le_bytes());
+            extensions.extend_from_slice(&extension.data);
+        }
+        if extensions.len() > MAX_EXTENSIONS_LEN {
+            return Err(invalid("Header extensions are too long"));
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(self.version);
+        bytes.push(self.algorithm.identifier());
+        bytes.push(self.nonce.len() as u8);
+        bytes.extend_from_slice(&self.nonce);
+        bytes.extend_from_slice(&self.flags.to_le_bytes());
+        bytes.extend_from_slice(&(extensions.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&extensions);
+        Ok(bytes)
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R, version: u8) -> Result<Self, CryptoError> { //reads everything after the magic and version
+        let algorithm = Algorithm::from_identifier(read_u8(reader)?).ok_or_else(|| invalid("Unknown algorithm identifier"))?;
+        let nonce_len = read_u8(reader)? as usize;
+        let nonce = read_bytes(reader, nonce_len)?;
+
+        let flags = read_u32(reader)?;
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(invalid("File uses features this version does not support"));
+        }
+
+        let extensions_len = read_u32(reader)? as usize;
+        if extensions_len > MAX_EXTENSIONS_LEN {
+            return Err(invalid("Header extensions are too long"));
+        }
+        let area = read_bytes(reader, extensions_len)?;
+        let mut area = area.as_slice();
+        let mut extensions = Vec::new();
+        while !area.is_empty() {
+            let kind = read_u16(&mut area)?;
+            let len = read_u32(&mut area)? as usize;
+            if len > area.len() {
+                return Err(invalid("Header extension is truncated"));
+            }
+            let (data, rest) = area.split_at(len);
+            extensions.push(Extension { kind, data: data.to_vec() });
+            area = rest;
+        }
+        if let Some(extension) = extensions.iter().find(|e| e.kind & CRITICAL_EXTENSION != 0 && !KNOWN_CRITICAL.contains(&e.kind)) {
+            return Err(invalid(&format!("File uses an unsupported header extension ({:#06x})", extension.kind)));
+        }
+
+        Ok(Self { version, algorithm, nonce, flags, extensions })
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::header::{FLAG_ARCHIVE, FLAG_CONTAINER, FLAG_METADATA, FLAG_MIRROR};
+use super::keyslot::KeySlot;
+use super::{credential_kinds, key_slots, open_encrypted, passphrase_kdfs, v0_algorithm, Algorithm, CredentialKind, CryptoError, Encrypted, Kdf, EXT_KEY_SLOT, EXT_SIGNATURE};
+use super::{NONCE_LEN, SALT_LEN};
+
+const V0_TAG_LEN: u64 = 16; //all algorithms from before the header have 16-byte tags, so even an empty file has one
+
+const FLAG_NAMES: &[(u32, &str)] = &[
+    (FLAG_METADATA, "metadata"),
+    (FLAG_ARCHIVE, "folder"),
+    (FLAG_CONTAINER, "archive of files"),
+    (FLAG_MIRROR, "mirror keys"),
+];
+
+// everything that can be learned about a file without its key, for telling which tool made it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inspection {
+    Native(HeaderInfo),
+    Unversioned { algorithm: Algorithm, salt: [u8; SALT_LEN], payload_len: u64 }, //nothing marks these, so this is only a likely guess
+    Age { credentials: Vec<CredentialKind>, payload_len: u64 },
+    OpenSsl { payload_len: u64 },
+    NotEncrypted, //not made by this tool, nor by age or openssl enc
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderInfo {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub kdfs: Vec<Kdf>, //those of the passphrase slots
+    pub salts: Vec<(CredentialKind, Vec<u8>)>, //those of the passphrase and raw key slots, in slot order
+    pub nonce_len: usize,
+    pub header_len: u64,
+    pub payload_len: u64,
+    pub key_slots: usize,
+    pub credentials: Vec<CredentialKind>,
+    pub flags: Vec<&'static str>,
+    pub signed: bool,
+}
+
+pub fn inspect_file(input_path: &Path) -> Result<Inspection, CryptoError> { //reads the header only, no key needed
+    let mut input = File::open(input_path)?;
+    let file_len = input.metadata()?.len();
+    let encrypted = match open_encrypted(&mut input) {
+        Ok(encrypted) => encrypted,
+        Err(CryptoError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(Inspection::NotEncrypted), //shorter than any magic
+        Err(e) => return Err(e), //our magic or age's, but a header this version can't read
+    };
+    Ok(match encrypted {
+        Encrypted::Versioned(header, _) => {
+            let header_len = header.to_bytes()?.len() as u64;
+            let mut kdfs = Vec::new();
+            for kdf in passphrase_kdfs(&header) {
+                if !kdfs.contains(&kdf) {
+                    kdfs.push(kdf);
+                }
+            }
+            let salts = key_slots(&header).into_iter().filter_map(|slot| match slot {
+                KeySlot::Passphrase { keyfile, salt, .. } => Some((CredentialKind::Passphrase { keyfile }, salt)),
+                KeySlot::Key { salt, .. } => Some((CredentialKind::Key, salt)),
+                KeySlot::X25519 { .. } => None, //wrapped with an ephemeral key instead
+            }).collect();
+            Inspection::Native(HeaderInfo {
+                version: header.version,
+                algorithm: header.algorithm,
+                kdfs,
+                salts,
+                nonce_len: header.nonce.len(),
+                header_len,
+                payload_len: file_len.saturating_sub(header_len),
+                key_slots: header.extensions_of(EXT_KEY_SLOT).count(),
+                credentials: credential_kinds(&header),
+                flags: FLAG_NAMES.iter().filter(|(flag, _)| header.flags & flag != 0).map(|(_, name)| *name).collect(),
+                signed: header.extension(EXT_SIGNATURE).is_some(),
+            })
+        },
+        Encrypted::Age(header, _) => Inspection::Age { credentials: header.credential_kinds(), payload_len: file_len.saturating_sub(header.encoded_len()) },
+        Encrypted::OpenSsl(_) => Inspection::OpenSsl { payload_len: file_len.saturating_sub(16) }, //magic and salt
+        Encrypted::Unversioned(mut input) => { //[algorithm id][salt][nonce][ciphertext], see decrypt_v0
+            let mut id = [0u8; 1];
+            input.read_exact(&mut id)?;
+            let header_len = (1 + SALT_LEN + NONCE_LEN) as u64;
+            match v0_algorithm(id[0]) {
+                Some(algorithm) if file_len >= header_len + V0_TAG_LEN => {
+                    let mut salt = [0u8; SALT_LEN];
+                    input.read_exact(&mut salt)?;
+                    Inspection::Unversioned { algorithm, salt, payload_len: file_len - header_len }
+                },
+                _ => Inspection::NotEncrypted,
+            }
+        },
+    })
+}
+
+fn credential_name(kind: &CredentialKind) -> &'static str {
+    match kind {
+        CredentialKind::Passphrase { keyfile: false } => "passphrase",
+        CredentialKind::Passphrase { keyfile: true } => "passphrase and keyfile",
+        CredentialKind::Key => "raw key",
+        CredentialKind::Identity => "identity",
+    }
+}
+
+fn kdf_description(kdf: &Kdf) -> String {
+    match kdf {
+        Kdf::None => String::from("none"),
+        Kdf::Pbkdf2 { iterations } => format!("PBKDF2-HMAC-SHA256, {} iterations", iterations),
+        Kdf::Argon2id { memory_kib, iterations, parallelism } => format!("Argon2id, {} MiB, {} iterations, {} lanes", memory_kib / 1024, iterations, parallelism),
+        Kdf::Scrypt { log_n, r, p } => format!("scrypt, N = 2^{}, r = {}, p = {}", log_n, r, p),
+    }
+}
+
+fn list(names: impl Iterator<Item = &'static str>) -> String {
+    let names: Vec<&str> = names.collect();
+    if names.is_empty() { String::from("none") } else { names.join(", ") }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inspection::Native(info) => {
+                writeln!(f, "FileEncryptor file, format version {}", info.version)?;
+                writeln!(f, "Algorithm: {}", info.algorithm.name())?;
+                writeln!(f, "Key derivation: {}", if info.kdfs.is_empty() { String::from("none") } else { info.kdfs.iter().map(kdf_description).collect::<Vec<_>>().join("; ") })?;
+                writeln!(f, "Salts: {}", if info.salts.is_empty() { String::from("none") } else {
+                    info.salts.iter().map(|(kind, salt)| format!("{} ({})", hex::encode(salt), credential_name(kind))).collect::<Vec<_>>().join(", ")
+                })?;
+                writeln!(f, "Nonce: {} bytes", info.nonce_len)?;
+                writeln!(f, "Header: {} bytes, payload: {} bytes", info.header_len, info.payload_len)?;
+                writeln!(f, "Key slots: {} ({})", info.key_slots, list(info.credentials.iter().map(credential_name)))?;
+                writeln!(f, "Flags: {}", list(info.flags.iter().copied()))?;
+                write!(f, "Signed: {}", if info.signed { "yes" } else { "no" })
+            },
+            Inspection::Unversioned { algorithm, salt, payload_len } => {
+                writeln!(f, "Unknown file, possibly an unversioned FileEncryptor file from before the header existed")?;
+                writeln!(f, "Nothing marks those, so this is only a guess from its first byte and length")?;
+                writeln!(f, "Algorithm: {}", algorithm.name())?;
+                writeln!(f, "Key derivation: {}", kdf_description(&Kdf::pbkdf2()))?;
+                writeln!(f, "Salt: {}", hex::encode(salt))?;
+                write!(f, "Payload: {} bytes", payload_len)
+            },
+            Inspection::Age { credentials, payload_len } => {
+                writeln!(f, "age v1 file")?;
+                writeln!(f, "Opens with: {}", list(credentials.iter().map(credential_name)))?;
+                write!(f, "Payload: {} bytes", payload_len)
+            },
+            Inspection::OpenSsl { payload_len } => {
+                writeln!(f, "OpenSSL enc file (salted)")?;
+                write!(f, "Payload: {} bytes", payload_len)
+            },
+            Inspection::NotEncrypted => write!(f, "Not a FileEncryptor file, nor an age or OpenSSL one"),
+        }
+    }
+}
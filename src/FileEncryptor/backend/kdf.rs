@@ -0,0 +1,176 @@
+use argon2::{Argon2, Params, Version};
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use scrypt::scrypt;
+use sha2::Sha256;
+use std::time::{Duration, Instant};
+
+use super::{CryptoError, ITERATIONS, KEY_LEN};
+
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_PARALLELISM: u32 = 4;
+const SCRYPT_LOG_N: u32 = 17;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+const MAX_ARGON2_MEMORY_KIB: u32 = 4 * 1024 * 1024; //refuse headers asking for more than 4 GiB
+const MAX_SCRYPT_MEMORY: u128 = 4 * 1024 * 1024 * 1024;
+const MAX_PBKDF2_ITERATIONS: u32 = 100_000_000; //and for more than a minute or so of work
+const MAX_ARGON2_ITERATIONS: u32 = 1_000;
+const MAX_ARGON2_WORK_KIB: u64 = 16 * 1024 * 1024; //memory times passes, each parameter within its limit can still add up to hours
+const MAX_SCRYPT_WORK: u128 = 16 * 1024 * 1024 * 1024; //128 * r * N * p, the bytes mixed in total
+const MIN_ARGON2_MEMORY_KIB: u32 = 8 * 1024;
+const MIN_SCRYPT_LOG_N: u32 = 10;
+const MAX_SCRYPT_LOG_N: u32 = 22;
+const MIN_MEASUREMENT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kdf {
+    None, //the secret already is the key, used for raw key files
+    Pbkdf2 { iterations: u32 },
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32 },
+    Scrypt { log_n: u32, r: u32, p: u32 },
+}
+
+impl Kdf {
+    pub fn pbkdf2() -> Self {
+        Kdf::Pbkdf2 { iterations: ITERATIONS }
+    }
+
+    pub fn argon2id() -> Self {
+        Kdf::Argon2id { memory_kib: ARGON2_MEMORY_KIB, iterations: ARGON2_ITERATIONS, parallelism: ARGON2_PARALLELISM }
+    }
+
+    pub fn scrypt() -> Self {
+        Kdf::Scrypt { log_n: SCRYPT_LOG_N, r: SCRYPT_R, p: SCRYPT_P }
+    }
+
+    // set id for each KDF and encode its parameters for the header
+    pub(super) fn identifier(&self) -> u8 {
+        match self {
+            Kdf::None => 0,
+            Kdf::Pbkdf2 { .. } => 1,
+            Kdf::Argon2id { .. } => 2,
+            Kdf::Scrypt { .. } => 3,
+        }
+    }
+
+    pub(super) fn params(&self) -> Vec<u8> {
+        match self {
+            Kdf::None => Vec::new(),
+            Kdf::Pbkdf2 { iterations } => iterations.to_le_bytes().to_vec(),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                [memory_kib, iterations, parallelism].iter().flat_map(|v| v.to_le_bytes()).collect()
+            }
+            Kdf::Scrypt { log_n, r, p } => [log_n, r, p].iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+
+    pub(super) fn from_identifier(id: u8, params: &[u8]) -> Option<Self> {
+        if !params.len().is_multiple_of(4) {
+            return None;
+        }
+        let words: Vec<u32> = params.chunks_exact(4).map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+        let kdf = match (id, words.as_slice()) {
+            (0, &[]) => Kdf::None,
+            (1, &[iterations]) => Kdf::Pbkdf2 { iterations },
+            (2, &[memory_kib, iterations, parallelism]) => Kdf::Argon2id { memory_kib, iterations, parallelism },
+            (3, &[log_n, r, p]) => Kdf::Scrypt { log_n, r, p },
+            _ => return None,
+        };
+        kdf.is_sane().then_some(kdf)
+    }
+
+    pub(super) fn is_sane(&self) -> bool { //keep a crafted header from making us hang or exhaust memory
+        match self {
+            Kdf::None => true,
+            Kdf::Pbkdf2 { iterations } => *iterations > 0 && *iterations <= MAX_PBKDF2_ITERATIONS,
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                *memory_kib <= MAX_ARGON2_MEMORY_KIB && *iterations > 0 && *iterations <= MAX_ARGON2_ITERATIONS && *parallelism > 0 && *parallelism <= 64
+                    && *memory_kib as u64 * *iterations as u64 <= MAX_ARGON2_WORK_KIB
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                *log_n > 0 && *log_n < 32 && *r > 0 && *p > 0 && *p <= 64
+                    && (128 * *r as u128) << *log_n <= MAX_SCRYPT_MEMORY
+                    && (128 * *r as u128 * *p as u128) << *log_n <= MAX_SCRYPT_WORK
+            }
+        }
+    }
+
+    pub fn derive_key(&self, secret: &[u8], salt: &[u8]) -> Result<[u8; KEY_LEN], CryptoError> {
+        let mut key = [0u8; KEY_LEN];
+        match self {
+            Kdf::None => {
+                if secret.len() != KEY_LEN {
+                    return Err(CryptoError::Kdf(format!("A raw key must be exactly {} bytes", KEY_LEN)));
+                }
+                key.copy_from_slice(secret);
+            }
+            Kdf::Pbkdf2 { iterations } => pbkdf2::<Hmac<Sha256>>(secret, salt, *iterations, &mut key),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = Params::new(*memory_kib, *iterations, *parallelism, Some(KEY_LEN)).map_err(|e| CryptoError::Kdf(e.to_string()))?;
+                Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(secret, salt, &mut key)
+                    .map_err(|e| CryptoError::Kdf(e.to_string()))?;
+            }
+            Kdf::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(*log_n as u8, *r, *p, KEY_LEN).map_err(|e| CryptoError::Kdf(e.to_string()))?;
+                scrypt(secret, salt, &params, &mut key).map_err(|e| CryptoError::Kdf(e.to_string()))?;
+            }
+        }
+        Ok(key)
+    }
+
+    fn measure(&self) -> Result<Duration, CryptoError> {
+        let start = Instant::now();
+        self.derive_key(b"calibration", &[0u8; 16])?;
+        Ok(start.elapsed())
+    }
+
+    // benchmark this kind of KDF on the current machine and scale its cost so one derivation takes about `target`
+    pub fn calibrate(&self, target: Duration) -> Result<Self, CryptoError> {
+        let target = target.as_secs_f64();
+        match self {
+            Kdf::None => Ok(Kdf::None),
+            Kdf::Pbkdf2 { .. } => {
+                let mut iterations = 10_000u32;
+                let mut elapsed = Kdf::Pbkdf2 { iterations }.measure()?;
+                while elapsed < MIN_MEASUREMENT && iterations < u32::MAX / 2 { //measure long enough for the timing to mean something
+                    iterations *= 2;
+                    elapsed = Kdf::Pbkdf2 { iterations }.measure()?;
+                }
+                let scaled = iterations as f64 * target / elapsed.as_secs_f64();
+                Ok(Kdf::Pbkdf2 { iterations: scaled.clamp(1_000.0, MAX_PBKDF2_ITERATIONS as f64) as u32 })
+            }
+            Kdf::Argon2id { memory_kib, parallelism, .. } => {
+                let single_pass = Kdf::Argon2id { memory_kib: *memory_kib, iterations: 1, parallelism: *parallelism }.measure()?.as_secs_f64();
+                if single_pass > target { //one pass is already too slow, so trade memory for time instead
+                    let scaled = *memory_kib as f64 * target / single_pass;
+                    let memory_kib = (scaled as u32).max(MIN_ARGON2_MEMORY_KIB).max(8 * parallelism);
+                    Ok(Kdf::Argon2id { memory_kib, iterations: 1, parallelism: *parallelism })
+                } else {
+                    let max_iterations = MAX_ARGON2_ITERATIONS.min((MAX_ARGON2_WORK_KIB / *memory_kib as u64).max(1) as u32);
+                    let iterations = (target / single_pass).floor().clamp(1.0, max_iterations as f64) as u32;
+                    Ok(Kdf::Argon2id { memory_kib: *memory_kib, iterations, parallelism: *parallelism })
+                }
+            }
+            Kdf::Scrypt { r, p, .. } => {
+                // the cost only grows in powers of two, so pick the closest one
+                let base = 14;
+                let elapsed = Kdf::Scrypt { log_n: base, r: *r, p: *p }.measure()?.as_secs_f64();
+                let mut log_n = (base as f64 + (target / elapsed).log2()).round().clamp(MIN_SCRYPT_LOG_N as f64, MAX_SCRYPT_LOG_N as f64) as u32;
+                while log_n > MIN_SCRYPT_LOG_N && !(Kdf::Scrypt { log_n, r: *r, p: *p }).is_sane() {
+                    log_n -= 1;
+                }
+                Ok(Kdf::Scrypt { log_n, r: *r, p: *p })
+            }
+        }
+    }
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Kdf::argon2id()
+    }
+}
@@ -0,0 +1,192 @@
+use aes_gcm::aead::OsRng;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use super::recipient::{self, Recipient};
+use super::{Credential, CredentialKind, CryptoError, Kdf, KEY_LEN, SALT_LEN};
+
+const SLOT_X25519: u8 = 1;
+const SLOT_PASSPHRASE: u8 = 2;
+const SLOT_KEY: u8 = 3;
+const SLOT_FLAG_KEYFILE: u8 = 0x01;
+const WRAPPED_LEN: usize = KEY_LEN + 16;
+const X25519_BODY_LEN: usize = 32 + WRAPPED_LEN;
+const RECIPIENT_TAG_LEN: usize = 4;
+const KEY_SLOT_INFO: &[u8] = b"FileEncryptor raw key slot";
+
+// every slot holds its own wrapped copy of the random file key, layouts after the kind byte:
+// X25519: ephemeral public key[32] | wrapped key | recipient tag[4]
+// passphrase: kdf id u8 | kdf params len u8 | kdf params | salt[16] | flags u8 | wrapped key
+// raw key: salt[16] | wrapped key
+#[derive(Debug, Clone)]
+pub enum KeySlot {
+    X25519 { body: Vec<u8>, tag: [u8; RECIPIENT_TAG_LEN] },
+    Passphrase { kdf: Kdf, salt: Vec<u8>, keyfile: bool, wrapped: Vec<u8> },
+    Key { salt: Vec<u8>, wrapped: Vec<u8> },
+}
+
+//every wrapping key is derived with a fresh salt or ephemeral key, so it is only ever used once and a fixed nonce is fine
+fn wrap(wrapping_key: &[u8; KEY_LEN], file_key: &[u8; KEY_LEN]) -> Vec<u8> {
+    ChaCha20Poly1305::new(wrapping_key.into())
+        .encrypt(&[0u8; 12].into(), file_key.as_slice())
+        .expect("sealing a 32-byte key cannot fail")
+}
+
+fn unwrap(wrapping_key: &[u8; KEY_LEN], wrapped: &[u8]) -> Option<[u8; KEY_LEN]> {
+    let file_key = ChaCha20Poly1305::new(wrapping_key.into()).decrypt(&[0u8; 12].into(), wrapped).ok()?;
+    file_key.try_into().ok()
+}
+
+fn raw_wrapping_key(key: &[u8; KEY_LEN], salt: &[u8]) -> [u8; KEY_LEN] { //raw keys are reused across files, so salt them
+    let mut wrapping_key = [0u8; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(salt), key).expand(KEY_SLOT_INFO, &mut wrapping_key).expect("32 bytes is a valid HKDF length");
+    wrapping_key
+}
+
+fn recipient_tag(recipient: &Recipient) -> [u8; RECIPIENT_TAG_LEN] { //just enough of the public key's hash to find its slot again
+    let mut tag = [0u8; RECIPIENT_TAG_LEN];
+    tag.copy_from_slice(&Sha256::digest(recipient.to_bytes())[..RECIPIENT_TAG_LEN]);
+    tag
+}
+
+fn random_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+impl KeySlot {
+    pub fn seal(credential: &Credential, kdf: &Kdf, file_key: &[u8; KEY_LEN]) -> Result<Self, CryptoError> {
+        match credential {
+            Credential::Passphrase { keyfile, .. } => {
+                if *kdf == Kdf::None {
+                    return Err(CryptoError::Kdf("A passphrase needs a key derivation function".to_string()));
+                }
+                let salt = random_salt();
+                let wrapping_key = kdf.derive_key(&credential.secret(keyfile.is_some())?, &salt)?;
+                Ok(KeySlot::Passphrase { kdf: kdf.clone(), salt, keyfile: keyfile.is_some(), wrapped: wrap(&wrapping_key, file_key) })
+            }
+            Credential::Key(key) => {
+                let salt = random_salt();
+                let wrapped = wrap(&raw_wrapping_key(key, &salt), file_key);
+                Ok(KeySlot::Key { salt, wrapped })
+            }
+            Credential::Recipient(recipient) => Ok(KeySlot::X25519 { body: recipient::wrap(recipient, file_key), tag: recipient_tag(recipient) }),
+            Credential::Identity(identity) => Self::seal(&Credential::Recipient(identity.to_recipient()), kdf, file_key), //encrypting to yourself
+        }
+    }
+
+    pub fn kind(&self) -> CredentialKind {
+        match self {
+            KeySlot::X25519 { .. } => CredentialKind::Identity,
+            KeySlot::Passphrase { keyfile, .. } => CredentialKind::Passphrase { keyfile: *keyfile },
+            KeySlot::Key { .. } => CredentialKind::Key,
+        }
+    }
+
+    fn accepts(&self, credential: &Credential) -> bool {
+        matches!(
+            (self, credential),
+            (KeySlot::X25519 { .. }, Credential::Identity(_)) | (KeySlot::Passphrase { .. }, Credential::Passphrase { .. }) | (KeySlot::Key { .. }, Credential::Key(_))
+        )
+    }
+
+    fn open(&self, credential: &Credential) -> Result<Option<[u8; KEY_LEN]>, CryptoError> { //None when the credential is wrong
+        match (self, credential) {
+            (KeySlot::X25519 { body, .. }, Credential::Identity(identity)) => Ok(recipient::unwrap(identity, body)),
+            (KeySlot::Passphrase { kdf, salt, keyfile, wrapped }, Credential::Passphrase { .. }) => {
+                let wrapping_key = kdf.derive_key(&credential.secret(*keyfile)?, salt)?;
+                Ok(unwrap(&wrapping_key, wrapped))
+            }
+            (KeySlot::Key { salt, wrapped }, Credential::Key(key)) => Ok(unwrap(&raw_wrapping_key(key, salt), wrapped)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn matches(&self, credential: &Credential) -> Result<bool, CryptoError> { //whether this is the slot of a credential being removed
+        match (self, credential) {
+            (KeySlot::X25519 { tag, .. }, Credential::Recipient(recipient)) => Ok(*tag == recipient_tag(recipient)),
+            (KeySlot::X25519 { tag, .. }, Credential::Identity(identity)) => Ok(*tag == recipient_tag(&identity.to_recipient())),
+            _ if !self.accepts(credential) => Ok(false),
+            _ => match self.open(credential) {
+                Ok(file_key) => Ok(file_key.is_some()),
+                Err(CryptoError::KeyfileRequired) => Ok(false),
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            KeySlot::X25519 { body, tag } => {
+                bytes.push(SLOT_X25519);
+                bytes.extend_from_slice(body);
+                bytes.extend_from_slice(tag);
+            }
+            KeySlot::Passphrase { kdf, salt, keyfile, wrapped } => {
+                let params = kdf.params();
+                bytes.push(SLOT_PASSPHRASE);
+                bytes.push(kdf.identifier());
+                bytes.push(params.len() as u8);
+                bytes.extend_from_slice(&params);
+                bytes.extend_from_slice(salt);
+                bytes.push(if *keyfile { SLOT_FLAG_KEYFILE } else { 0 });
+                bytes.extend_from_slice(wrapped);
+            }
+            KeySlot::Key { salt, wrapped } => {
+                bytes.push(SLOT_KEY);
+                bytes.extend_from_slice(salt);
+                bytes.extend_from_slice(wrapped);
+            }
+        }
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Self> { //None for malformed slots or kinds this version doesn't know
+        let (&kind, rest) = bytes.split_first()?;
+        match kind {
+            SLOT_X25519 if rest.len() == X25519_BODY_LEN + RECIPIENT_TAG_LEN => {
+                let (body, tag) = rest.split_at(X25519_BODY_LEN);
+                Some(KeySlot::X25519 { body: body.to_vec(), tag: tag.try_into().ok()? })
+            }
+            SLOT_PASSPHRASE => {
+                let (&kdf_id, rest) = rest.split_first()?;
+                let (&params_len, rest) = rest.split_first()?;
+                if rest.len() != params_len as usize + SALT_LEN + 1 + WRAPPED_LEN {
+                    return None;
+                }
+                let (params, rest) = rest.split_at(params_len as usize);
+                let (salt, rest) = rest.split_at(SALT_LEN);
+                let kdf = Kdf::from_identifier(kdf_id, params).filter(|kdf| *kdf != Kdf::None)?;
+                Some(KeySlot::Passphrase { kdf, salt: salt.to_vec(), keyfile: rest[0] & SLOT_FLAG_KEYFILE != 0, wrapped: rest[1..].to_vec() })
+            }
+            SLOT_KEY if rest.len() == SALT_LEN + WRAPPED_LEN => {
+                let (salt, wrapped) = rest.split_at(SALT_LEN);
+                Some(KeySlot::Key { salt: salt.to_vec(), wrapped: wrapped.to_vec() })
+            }
+            _ => None,
+        }
+    }
+}
+
+// try every slot the credential could open, like LUKS does with its keyslots
+pub fn open_any(slots: &[KeySlot], credential: &Credential) -> Result<[u8; KEY_LEN], CryptoError> {
+    let mut error = None;
+    for slot in slots.iter().filter(|slot| slot.accepts(credential)) {
+        match slot.open(credential) {
+            Ok(Some(file_key)) => return Ok(file_key),
+            Ok(None) => { error.get_or_insert(CryptoError::WrongKey); }
+            Err(CryptoError::KeyfileRequired) => error = Some(CryptoError::KeyfileRequired), //the passphrase may well be right for this slot
+            Err(e) => return Err(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| match slots.first().map(KeySlot::kind) { //nothing in the file takes this kind of credential
+        Some(CredentialKind::Identity) => CryptoError::IdentityRequired,
+        Some(CredentialKind::Key) => CryptoError::KeyRequired,
+        _ => CryptoError::PassphraseRequired,
+    }))
+}
@@ -0,0 +1,204 @@
+use std::ffi::OsString;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const FIELD_NAME: u8 = 1;
+const FIELD_SIZE: u8 = 2;
+const FIELD_MODIFIED: u8 = 3;
+const FIELD_ACCESSED: u8 = 4;
+const FIELD_MODE: u8 = 5;
+const FIELD_OWNER: u8 = 6;
+const MAX_METADATA_LEN: u32 = 64 * 1024;
+
+// sealed at the start of the payload when the header has FLAG_METADATA: len u32 | fields,
+// each field is tag u8 | len u16 | data, unknown tags are skipped so more can be added later
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: Option<OsString>,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+    pub mode: Option<u32>, //unix permission bits
+    pub owner: Option<(u32, u32)>, //unix uid and gid, only kept when asked for
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn time_to_bytes(time: SystemTime) -> Vec<u8> { //seconds i64 | nanoseconds u32, relative to the unix epoch
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    [secs.to_le_bytes().as_slice(), &nanos.to_le_bytes()].concat()
+}
+
+fn time_from_bytes(bytes: &[u8]) -> Option<SystemTime> {
+    let secs = i64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+    let nanos = u32::from_le_bytes(bytes.get(8..12)?.try_into().ok()?);
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let since = Duration::new(secs.unsigned_abs(), 0);
+    let time = if secs >= 0 { UNIX_EPOCH.checked_add(since)? } else { UNIX_EPOCH.checked_sub(since)? };
+    time.checked_add(Duration::from_nanos(nanos as u64))
+}
+
+#[cfg(unix)]
+pub(super) fn name_to_bytes(name: &OsString) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub(super) fn name_to_bytes(name: &OsString) -> Vec<u8> {
+    name.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+pub(super) fn name_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).to_os_string()
+}
+
+#[cfg(not(unix))]
+pub(super) fn name_from_bytes(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+impl Metadata {
+    pub fn from_file(path: &Path, keep_owner: bool) -> io::Result<Self> {
+        let stat = fs::metadata(path)?;
+        let mut metadata = Metadata {
+            name: path.file_name().map(|name| name.to_os_string()),
+            size: Some(stat.len()),
+            modified: stat.modified().ok(),
+            accessed: stat.accessed().ok(),
+            ..Default::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            metadata.mode = Some(stat.mode() & 0o7777);
+            metadata.owner = keep_owner.then(|| (stat.uid(), stat.gid()));
+        }
+        #[cfg(not(unix))]
+        let _ = keep_owner; //there is no owner to keep
+        Ok(metadata)
+    }
+
+    pub fn write_to<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut fields = Vec::new();
+        let mut field = |tag: u8, data: &[u8]| {
+            fields.push(tag);
+            fields.extend_from_slice(&(data.len() as u16).to_le_bytes());
+            fields.extend_from_slice(data);
+        };
+        if let Some(name) = &self.name {
+            let name = name_to_bytes(name);
+            field(FIELD_NAME, &name[..name.len().min(u16::MAX as usize)]);
+        }
+        if let Some(size) = self.size {
+            field(FIELD_SIZE, &size.to_le_bytes());
+        }
+        if let Some(modified) = self.modified {
+            field(FIELD_MODIFIED, &time_to_bytes(modified));
+        }
+        if let Some(accessed) = self.accessed {
+            field(FIELD_ACCESSED, &time_to_bytes(accessed));
+        }
+        if let Some(mode) = self.mode {
+            field(FIELD_MODE, &mode.to_le_bytes());
+        }
+        if let Some((uid, gid)) = self.owner {
+            field(FIELD_OWNER, &[uid.to_le_bytes(), gid.to_le_bytes()].concat());
+        }
+        output.write_all(&(fields.len() as u32).to_le_bytes())?;
+        output.write_all(&fields)
+    }
+
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut len = [0u8; 4];
+        input.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len);
+        if len > MAX_METADATA_LEN {
+            return Err(invalid("Metadata block is too large"));
+        }
+        let mut fields = vec![0u8; len as usize];
+        input.read_exact(&mut fields)?;
+
+        let mut metadata = Metadata::default();
+        let mut rest = fields.as_slice();
+        while let [tag, len_low, len_high, tail @ ..] = rest {
+            let len = u16::from_le_bytes([*len_low, *len_high]) as usize;
+            if tail.len() < len {
+                return Err(invalid("Metadata block is malformed"));
+            }
+            let (data, tail) = tail.split_at(len);
+            match *tag {
+                FIELD_NAME => metadata.name = Some(name_from_bytes(data)),
+                FIELD_SIZE => metadata.size = data.try_into().ok().map(u64::from_le_bytes),
+                FIELD_MODIFIED => metadata.modified = time_from_bytes(data),
+                FIELD_ACCESSED => metadata.accessed = time_from_bytes(data),
+                FIELD_MODE => metadata.mode = data.try_into().ok().map(u32::from_le_bytes),
+                FIELD_OWNER if len == 8 => metadata.owner = Some((u32::from_le_bytes(data[..4].try_into().unwrap()), u32::from_le_bytes(data[4..].try_into().unwrap()))),
+                _ => (), //written by a newer version
+            }
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(invalid("Metadata block is malformed"));
+        }
+        Ok(metadata)
+    }
+
+    pub fn apply(&self, file: &File) -> io::Result<()> { //put the times, mode and owner back on a decrypted file
+        let mut times = FileTimes::new();
+        if let Some(modified) = self.modified {
+            times = times.set_modified(modified);
+        }
+        if let Some(accessed) = self.accessed {
+            times = times.set_accessed(accessed);
+        }
+        file.set_times(times)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let owner_restored = match self.owner { //before the mode, changing the owner can clear setuid bits
+                Some((uid, gid)) => match std::os::unix::fs::fchown(file, Some(uid), Some(gid)) {
+                    Err(e) if e.kind() == io::ErrorKind::PermissionDenied => false, //only root may give files away
+                    result => result.map(|_| true)?,
+                },
+                None => false,
+            };
+            if let Some(mode) = self.mode { //setuid, setgid and sticky only come back with their owner, never on a file of whoever decrypts it
+                let mode = if owner_restored { mode } else { mode & 0o777 };
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn restore_name(path: &Path, metadata: &Metadata) -> io::Result<std::path::PathBuf> { //rename a decrypted file back to its original name, next to where it is
+    let Some(name) = metadata.name.as_ref().filter(|name| Path::new(name).file_name() == Some(name.as_os_str())) else {
+        return Ok(path.to_path_buf()); //no name, or one that would leave the directory
+    };
+    let restored = path.with_file_name(name);
+    if restored != path {
+        if restored.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", restored)));
+        }
+        fs::rename(path, &restored)?;
+    }
+    Ok(restored)
+}
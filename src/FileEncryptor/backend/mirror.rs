@@ -0,0 +1,209 @@
+use aes_siv::siv::Aes256Siv;
+use aes_siv::KeyInit;
+use data_encoding::BASE32_NOPAD;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::header::FLAG_MIRROR;
+use super::metadata::{name_from_bytes, name_to_bytes};
+use super::{create_decrypted_file, decrypt_versioned, encrypt_file, new_header, open_encrypted, unlock, write_atomically};
+use super::{Algorithm, Credential, CryptoError, EncryptOptions, Encrypted, Kdf, KEY_LEN};
+
+const CONFIG_NAME: &str = "fileencryptor.mirror";
+const MAX_NAME_LEN: usize = 255; //what most filesystems allow for one path component
+const NAME_KEY_LEN: usize = 64; //AES-SIV takes a CMAC key and a CTR key, both for AES-256
+const NAME_KEY_INFO: &[u8] = b"FileEncryptor mirror names";
+const CONTENT_KEY_INFO: &[u8] = b"FileEncryptor mirror contents";
+
+// a mirror is a folder with the same shape as the source, where every file is encrypted on its own and every
+// file and folder name is encrypted with AES-SIV and written as base32. The config file at its root is a header
+// with the key slots and no payload; the key they unlock gives the name key and the content key. Every file is
+// locked with a raw key derived from the content key and its plaintext path, so a file's contents can't be swapped
+// with another's, or moved to another name, without restoring it failing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub encrypted: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub skipped: usize, //symlinks and special files, which aren't mirrored
+    pub too_long: Vec<PathBuf>, //files and folders whose encrypted name wouldn't fit, left out of the mirror
+}
+
+struct Keys {
+    names: [u8; NAME_KEY_LEN],
+    content: [u8; KEY_LEN],
+    algorithm: Algorithm,
+}
+
+impl Keys {
+    fn siv(&self) -> Aes256Siv { //AES-SIV from RFC 5297, deterministic so the same name always encrypts the same way
+        Aes256Siv::new_from_slice(&self.names).expect("a 512-bit AES-SIV key")
+    }
+
+    // the parent's plaintext path goes in as associated data, so the same name in two folders looks different
+    fn encrypt_name(&self, parent: &[u8], name: &OsString) -> Option<OsString> { //None when it would be too long for the filesystem
+        let encrypted = BASE32_NOPAD.encode(&self.siv().encrypt([parent], &name_to_bytes(name)).expect("a single header"));
+        (encrypted.len() <= MAX_NAME_LEN).then(|| encrypted.into())
+    }
+
+    fn decrypt_name(&self, parent: &[u8], encrypted: &OsString) -> Option<OsString> { //None for anything that isn't one of ours
+        let ciphertext = BASE32_NOPAD.decode(encrypted.to_str()?.as_bytes()).ok()?;
+        let name = name_from_bytes(&self.siv().decrypt([parent], &ciphertext).ok()?);
+        (Path::new(&name).file_name() == Some(name.as_os_str())).then_some(name) //never a path that leaves the folder
+    }
+
+    fn file_key(&self, path: &[u8]) -> Credential { //path is the file's plaintext path within the mirror
+        let mut key = [0u8; KEY_LEN];
+        Hkdf::<Sha256>::new(None, &self.content).expand(path, &mut key).expect("32 bytes is a valid HKDF length");
+        Credential::Key(key)
+    }
+}
+
+fn child_path(parent: &[u8], name: &OsString) -> Vec<u8> {
+    let mut path = parent.to_vec();
+    if !path.is_empty() {
+        path.push(b'/');
+    }
+    path.extend_from_slice(&name_to_bytes(name));
+    path
+}
+
+fn open_mirror(mirror: &Path, credential: &Credential) -> Result<Keys, CryptoError> {
+    let header = match open_encrypted(File::open(mirror_config(mirror))?)? {
+        Encrypted::Versioned(header, _) if header.flags & FLAG_MIRROR != 0 => header,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a mirrored folder").into()),
+    };
+    let key = unlock(&header, credential)?;
+    let hkdf = Hkdf::<Sha256>::new(None, &key);
+    let mut name_key = [0u8; NAME_KEY_LEN];
+    hkdf.expand(NAME_KEY_INFO, &mut name_key).expect("64 bytes is a valid HKDF length");
+    let mut content_key = [0u8; KEY_LEN];
+    hkdf.expand(CONTENT_KEY_INFO, &mut content_key).expect("32 bytes is a valid HKDF length");
+    Ok(Keys { names: name_key, content: content_key, algorithm: header.algorithm })
+}
+
+pub fn mirror_config(mirror: &Path) -> PathBuf { //the header holding the mirror's key slots
+    mirror.join(CONFIG_NAME)
+}
+
+pub fn is_mirror(path: &Path) -> bool {
+    mirror_config(path).is_file()
+}
+
+pub fn create_mirror(mirror: &Path, credentials: &[Credential], algorithm: Algorithm, kdf: Kdf) -> Result<(), CryptoError> { //an empty mirror, filled by sync_mirror
+    if mirror.exists() && fs::read_dir(mirror)?.next().is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "The mirror folder must be empty").into());
+    }
+    let (header, _) = new_header(credentials, algorithm, kdf, EncryptOptions::default(), FLAG_MIRROR)?;
+    fs::create_dir_all(mirror)?;
+    write_atomically(&mirror_config(mirror), |output| Ok(output.write_all(&header.to_bytes()?)?))
+}
+
+// brings the mirror up to date with the source: new and changed files are encrypted, unchanged ones are left alone
+// and files that are gone from the source are removed. A file counts as unchanged when its encrypted copy carries
+// the same modification time, which is set on it after encrypting, and the size sealed in it matches
+pub fn sync_mirror(source: &Path, mirror: &Path, credential: &Credential, options: EncryptOptions) -> Result<SyncReport, CryptoError> {
+    let keys = open_mirror(mirror, credential)?;
+    let mut report = SyncReport::default();
+    sync_folder(&keys, source, mirror, b"", options, &mut report)?;
+    Ok(report)
+}
+
+fn sync_folder(keys: &Keys, source: &Path, mirror: &Path, parent: &[u8], options: EncryptOptions, report: &mut SyncReport) -> Result<(), CryptoError> {
+    let mut expected = HashSet::new();
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let file_type = entry.file_type()?; //symlinks are not followed
+        if !file_type.is_dir() && !file_type.is_file() {
+            report.skipped += 1;
+            continue;
+        }
+        let Some(encrypted_name) = keys.encrypt_name(parent, &name) else {
+            report.too_long.push(entry.path());
+            continue;
+        };
+        let target = mirror.join(&encrypted_name);
+        expected.insert(encrypted_name);
+
+        if file_type.is_dir() {
+            if target.is_file() { //was a file in the last sync
+                fs::remove_file(&target)?;
+            }
+            if !target.is_dir() {
+                fs::create_dir(&target)?;
+            }
+            sync_folder(keys, &entry.path(), &target, &child_path(parent, &name), options, report)?;
+            continue;
+        }
+        let file_key = keys.file_key(&child_path(parent, &name));
+        let metadata = entry.metadata()?;
+        let modified = metadata.modified()?;
+        if fs::metadata(&target).and_then(|target| target.modified()).is_ok_and(|copy| copy == modified) && sealed_size(&file_key, &target) == Some(metadata.len()) {
+            report.unchanged += 1;
+            continue;
+        }
+        if target.is_dir() { //was a folder in the last sync
+            fs::remove_dir_all(&target)?;
+        }
+        encrypt_file(&entry.path(), &target, std::slice::from_ref(&file_key), keys.algorithm, Kdf::None, options)?;
+        File::options().write(true).open(&target)?.set_modified(modified)?;
+        report.encrypted += 1;
+    }
+
+    for entry in fs::read_dir(mirror)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if (parent.is_empty() && name == CONFIG_NAME) || expected.contains(&name) || keys.decrypt_name(parent, &name).is_none() {
+            continue; //files that weren't put there by a sync are left alone
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+        report.removed += 1;
+    }
+    Ok(())
+}
+
+fn sealed_size(file_key: &Credential, copy: &Path) -> Option<u64> { //only the first segment is opened to get at the metadata
+    let Encrypted::Versioned(header, input) = open_encrypted(File::open(copy).ok()?).ok()? else { return None };
+    decrypt_versioned(header, input, file_key, |_, _| Ok(())).ok()??.size
+}
+
+pub fn restore_mirror(mirror: &Path, output: &Path, credential: &Credential) -> Result<usize, CryptoError> { //decrypts the whole mirror into a new folder, returns how many files it held
+    let keys = open_mirror(mirror, credential)?;
+    if output.exists() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", output)).into());
+    }
+    fs::create_dir_all(output)?;
+    restore_folder(&keys, mirror, output, b"")
+}
+
+fn restore_folder(keys: &Keys, mirror: &Path, output: &Path, parent: &[u8]) -> Result<usize, CryptoError> {
+    let mut restored = 0;
+    for entry in fs::read_dir(mirror)? {
+        let entry = entry?;
+        let Some(name) = keys.decrypt_name(parent, &entry.file_name()) else {
+            continue; //the config file and anything else that isn't ours
+        };
+        let target = output.join(&name);
+        if entry.file_type()?.is_dir() {
+            fs::create_dir(&target)?;
+            restored += restore_folder(keys, &entry.path(), &target, &child_path(parent, &name))?;
+        } else {
+            match create_decrypted_file(&entry.path(), &target, &keys.file_key(&child_path(parent, &name))) {
+                Err(CryptoError::WrongKey) => return Err(CryptoError::Tampered), //sealed for another path
+                result => result?,
+            };
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
@@ -0,0 +1,105 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes256;
+use aes_gcm::aead::OsRng;
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use rand::RngCore;
+use sha2::Sha256;
+use std::io::{self, Read, Write};
+
+use super::{CryptoError, KEY_LEN};
+
+pub const MAGIC: [u8; 8] = *b"Salted__";
+const SALT_LEN: usize = 8;
+const BLOCK_LEN: usize = 16;
+const ITERATIONS: u32 = 10_000; //the default of `openssl enc -pbkdf2`
+const CHUNK_LEN: usize = 64 * 1024;
+
+// `openssl enc -aes-256-cbc -pbkdf2 -salt`: "Salted__" | salt[8] | AES-256-CBC with PKCS#7 padding,
+// key and IV both come from PBKDF2-HMAC-SHA256 over the passphrase. There is no MAC, so a wrong passphrase
+// and a modified file can only be told apart by the padding, and not always
+fn derive_key_and_iv(passphrase: &str, salt: &[u8]) -> (Aes256, [u8; BLOCK_LEN]) {
+    let mut derived = [0u8; KEY_LEN + BLOCK_LEN];
+    pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, ITERATIONS, &mut derived);
+    let iv = derived[KEY_LEN..].try_into().expect("derived IV length");
+    (Aes256::new(GenericArray::from_slice(&derived[..KEY_LEN])), iv)
+}
+
+fn read_chunk<R: Read>(input: &mut R, buffer: &mut Vec<u8>) -> io::Result<bool> { //adds up to a chunk to the buffer, true once the input has ended
+    let start = buffer.len();
+    buffer.resize(start + CHUNK_LEN, 0);
+    let mut filled = start;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let end = filled < buffer.len();
+    buffer.truncate(filled);
+    Ok(end)
+}
+
+pub fn encrypt<R: Read, W: Write>(mut input: R, mut output: W, passphrase: &str) -> Result<(), CryptoError> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let (cipher, mut previous) = derive_key_and_iv(passphrase, &salt);
+    output.write_all(&MAGIC)?;
+    output.write_all(&salt)?;
+
+    let mut buffer = Vec::new();
+    loop {
+        let done = read_chunk(&mut input, &mut buffer)?;
+        if done { //PKCS#7 always adds between 1 and 16 bytes
+            let padding = BLOCK_LEN - buffer.len() % BLOCK_LEN;
+            buffer.resize(buffer.len() + padding, padding as u8);
+        }
+        for block in buffer.chunks_exact_mut(BLOCK_LEN) {
+            block.iter_mut().zip(previous).for_each(|(byte, chained)| *byte ^= chained);
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            previous.copy_from_slice(block);
+        }
+        output.write_all(&buffer)?;
+        buffer.clear();
+        if done {
+            break;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+pub fn decrypt<R: Read, W: Write>(mut input: R, passphrase: &str, output: &mut W) -> Result<(), CryptoError> { //the magic has already been read
+    let mut salt = [0u8; SALT_LEN];
+    input.read_exact(&mut salt)?;
+    let (cipher, mut previous) = derive_key_and_iv(passphrase, &salt);
+
+    let mut buffer = Vec::new();
+    loop {
+        let done = read_chunk(&mut input, &mut buffer)?; //the last block is held back until we know it is the last
+        if buffer.len() % BLOCK_LEN != 0 || (done && buffer.is_empty()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "OpenSSL file is truncated").into());
+        }
+        let len = if done { buffer.len() } else { buffer.len() - BLOCK_LEN };
+        for block in buffer[..len].chunks_exact_mut(BLOCK_LEN) {
+            let ciphertext: [u8; BLOCK_LEN] = block.try_into().expect("block length");
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+            block.iter_mut().zip(previous).for_each(|(byte, chained)| *byte ^= chained);
+            previous = ciphertext;
+        }
+
+        if done {
+            let padding = buffer[len - 1] as usize;
+            if padding == 0 || padding > BLOCK_LEN || buffer[len - padding..len].iter().any(|&byte| byte as usize != padding) {
+                return Err(CryptoError::WrongKey); //without a MAC, bad padding is all a wrong passphrase leaves behind
+            }
+            output.write_all(&buffer[..len - padding])?;
+            return Ok(());
+        }
+        output.write_all(&buffer[..len])?;
+        buffer.drain(..len);
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{self, Read, Write};
+
+use super::CryptoError;
+
+const MARKER: u8 = 0x80;
+const ZEROS: [u8; 4096] = [0; 4096];
+
+// the padding goes at the end of the sealed payload: 0x80 then zeros up to the padded length, so the reader
+// can strip it without knowing the real length up front (the last 0x80 before only zeros is the marker)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    Padme, //at most about 12% overhead, leaks O(log log n) bits of the length
+    PowerOfTwo, //up to 100% overhead, leaks only O(log n) bits
+}
+
+impl Padding {
+    pub(super) fn identifier(self) -> u8 {
+        match self {
+            Padding::Padme => 1,
+            Padding::PowerOfTwo => 2,
+        }
+    }
+
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
+        match bytes {
+            [1] => Ok(Padding::Padme),
+            [2] => Ok(Padding::PowerOfTwo),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported padding in header").into()),
+        }
+    }
+
+    fn padded_len(self, len: u64) -> u64 {
+        match self {
+            Padding::Padme if len < 2 => len,
+            Padding::Padme => { //keep the top bits of the length and round the rest up, from the PURBs paper
+                let exponent = 63 - len.leading_zeros() as u64;
+                let exponent_bits = 64 - exponent.leading_zeros() as u64;
+                let mask = (1u64 << (exponent - exponent_bits)) - 1;
+                (len + mask) & !mask
+            }
+            Padding::PowerOfTwo => len.checked_next_power_of_two().unwrap_or(len),
+        }
+    }
+}
+
+pub struct PadWriter<W: Write> { //counts what goes through so the padding can be sized at the end
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> PadWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    pub fn into_inner(self) -> W { //no padding after all
+        self.inner
+    }
+
+    pub fn finish(mut self, padding: Padding) -> io::Result<W> {
+        self.inner.write_all(&[MARKER])?;
+        let mut zeros = padding.padded_len(self.written + 1) - self.written - 1;
+        while zeros > 0 {
+            let n = zeros.min(ZEROS.len() as u64) as usize;
+            self.inner.write_all(&ZEROS[..n])?;
+            zeros -= n as u64;
+        }
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for PadWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct UnpadReader<R: Read> { //holds back a trailing 0x80 and the zeros after it until it knows whether they are the padding
+    inner: R,
+    marker: bool,
+    zeros: u64,
+    released_marker: bool, //held bytes that turned out to be content, handed out before the buffer
+    released_zeros: u64, //counted rather than copied as the run can be long
+    buffer: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> UnpadReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, marker: false, zeros: 0, released_marker: false, released_zeros: 0, buffer: Vec::new(), position: 0, done: false }
+    }
+
+    fn fill(&mut self) -> io::Result<()> { //reads until there is something to hand out or the input has ended
+        let mut chunk = vec![0u8; 64 * 1024];
+        self.buffer.clear();
+        self.position = 0;
+        while self.buffer.is_empty() && !self.released_marker && !self.done {
+            let n = match self.inner.read(&mut chunk) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                if !self.marker { //the padding always starts with the marker
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Padding is missing"));
+                }
+                self.done = true;
+                break;
+            }
+            let chunk = &chunk[..n];
+            match chunk.iter().rposition(|&byte| byte != 0) {
+                None if self.marker => self.zeros += n as u64,
+                None => self.buffer.extend_from_slice(chunk),
+                Some(last) => {
+                    if self.marker { //what was held is content after all
+                        self.released_marker = true;
+                        self.released_zeros = self.zeros;
+                    }
+                    let marker = chunk[last] == MARKER; //may be the start of the padding
+                    self.buffer.extend_from_slice(if marker { &chunk[..last] } else { chunk });
+                    self.marker = marker;
+                    self.zeros = if marker { (n - last - 1) as u64 } else { 0 };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for UnpadReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.released_marker && self.released_zeros == 0 && self.position == self.buffer.len() {
+            self.fill()?;
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.released_marker {
+            buf[0] = MARKER;
+            self.released_marker = false;
+            return Ok(1);
+        }
+        if self.released_zeros > 0 {
+            let n = (buf.len() as u64).min(self.released_zeros) as usize;
+            buf[..n].fill(0);
+            self.released_zeros -= n as u64;
+            return Ok(n);
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,119 @@
+use aes_gcm::aead::OsRng;
+use bech32::{FromBase32, ToBase32, Variant};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use super::KEY_LEN;
+
+// same bech32 encoding as age, so one identity can be used with both tools, signing keys use it too
+const RECIPIENT_HRP: &str = "age";
+const IDENTITY_HRP: &str = "age-secret-key-";
+const WRAP_INFO: &[u8] = b"FileEncryptor X25519 key slot";
+const WRAPPED_LEN: usize = KEY_LEN + 16;
+const SLOT_BODY_LEN: usize = 32 + WRAPPED_LEN;
+
+#[derive(Clone)]
+pub struct Identity(StaticSecret); //an X25519 private key
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient(PublicKey); //an X25519 public key that files can be encrypted to
+
+impl Identity {
+    pub fn generate() -> Self {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let bytes = decode(text.trim(), IDENTITY_HRP)?;
+        Some(Identity(StaticSecret::from(bytes)))
+    }
+
+    pub fn to_recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+
+    pub(super) fn diffie_hellman(&self, public: &[u8; 32]) -> Option<[u8; 32]> {
+        let shared = self.0.diffie_hellman(&PublicKey::from(*public));
+        shared.was_contributory().then(|| shared.to_bytes()) //refuse low order points that force a known secret
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode(IDENTITY_HRP, &self.0.to_bytes()).to_uppercase())
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identity({})", self.to_recipient()) //never print the secret
+    }
+}
+
+impl Recipient {
+    pub fn parse(text: &str) -> Option<Self> {
+        Some(Recipient(PublicKey::from(decode(text.trim(), RECIPIENT_HRP)?)))
+    }
+
+    pub(super) fn to_bytes(self) -> [u8; 32] {
+        self.0.to_bytes()
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode(RECIPIENT_HRP, self.0.as_bytes()))
+    }
+}
+
+pub(super) fn encode(hrp: &str, bytes: &[u8; 32]) -> String {
+    bech32::encode(hrp, bytes.to_base32(), Variant::Bech32).expect("valid human readable part")
+}
+
+pub(super) fn decode(text: &str, hrp: &str) -> Option<[u8; 32]> {
+    let (found, data, variant) = bech32::decode(text).ok()?;
+    if found != hrp || variant != Variant::Bech32 {
+        return None;
+    }
+    Vec::<u8>::from_base32(&data).ok()?.try_into().ok()
+}
+
+fn wrapping_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> ChaCha20Poly1305 {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral);
+    salt[32..].copy_from_slice(recipient);
+    let mut key = [0u8; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(&salt), shared).expand(WRAP_INFO, &mut key).expect("32 bytes is a valid HKDF length");
+    ChaCha20Poly1305::new(&key.into())
+}
+
+// ephemeral-static ECDH: ephemeral public key[32] | file key sealed under a key derived from the shared secret
+pub fn wrap(recipient: &Recipient, file_key: &[u8; KEY_LEN]) -> Vec<u8> {
+    let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&recipient.0);
+    //each wrapping key is only ever used once, so a fixed nonce is fine
+    let wrapped = wrapping_key(shared.as_bytes(), ephemeral_public.as_bytes(), recipient.0.as_bytes())
+        .encrypt(&[0u8; 12].into(), file_key.as_slice())
+        .expect("sealing a 32-byte key cannot fail");
+
+    let mut body = ephemeral_public.as_bytes().to_vec();
+    body.extend_from_slice(&wrapped);
+    body
+}
+
+pub fn unwrap(identity: &Identity, body: &[u8]) -> Option<[u8; KEY_LEN]> {
+    if body.len() != SLOT_BODY_LEN {
+        return None;
+    }
+    let ephemeral: [u8; 32] = body[..32].try_into().ok()?;
+    let shared = identity.diffie_hellman(&ephemeral)?;
+    let file_key = wrapping_key(&shared, &ephemeral, &identity.to_recipient().to_bytes())
+        .decrypt(&[0u8; 12].into(), &body[32..])
+        .ok()?;
+    file_key.try_into().ok()
+}
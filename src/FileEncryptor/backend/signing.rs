@@ -0,0 +1,91 @@
+use aes_gcm::aead::OsRng;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey, SIGNATURE_LENGTH};
+use sha2::{Digest, Sha512};
+use std::fmt;
+
+use super::recipient::{decode, encode};
+
+const SIGNER_HRP: &str = "fesign";
+const SIGNING_KEY_HRP: &str = "fesign-secret-key-";
+const CONTEXT: &[u8] = b"FileEncryptor signature v1";
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = PUBLIC_KEY_LEN + SIGNATURE_LENGTH; //how a signature is stored: signer's public key | Ed25519 signature
+
+#[derive(Clone)]
+pub struct SigningIdentity(SigningKey); //an Ed25519 private key for signing encrypted files
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signer(VerifyingKey); //the public half, which is what a verified file reports
+
+// the file is hashed with SHA-512 as it is streamed and only the hash is signed, prefixed with what kind of
+// signature it is so an embedded one can't be passed off as a detached one or the other way round
+pub struct SignatureHasher(Sha512);
+
+impl SignatureHasher {
+    pub fn new(kind: &[u8]) -> Self {
+        let mut hasher = Sha512::new();
+        hasher.update(CONTEXT);
+        hasher.update([kind.len() as u8]);
+        hasher.update(kind);
+        SignatureHasher(hasher)
+    }
+
+    pub fn sign(self, identity: &SigningIdentity) -> Vec<u8> {
+        let signature = identity.0.sign(&self.0.finalize());
+        [identity.to_signer().0.as_bytes().as_slice(), &signature.to_bytes()].concat()
+    }
+
+    pub fn verify(self, stored: &[u8]) -> Option<Signer> { //the signer, if the signature is good
+        if stored.len() != SIGNATURE_LEN {
+            return None;
+        }
+        let (public, signature) = stored.split_at(PUBLIC_KEY_LEN);
+        let public = VerifyingKey::from_bytes(public.try_into().ok()?).ok()?;
+        let signature = Signature::from_bytes(signature.try_into().ok()?);
+        public.verify_strict(&self.0.finalize(), &signature).ok()?;
+        Some(Signer(public))
+    }
+}
+
+impl std::io::Write for SignatureHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SigningIdentity {
+    pub fn generate() -> Self {
+        SigningIdentity(SigningKey::generate(&mut OsRng))
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        Some(SigningIdentity(SigningKey::from_bytes(&decode(text.trim(), SIGNING_KEY_HRP)?)))
+    }
+
+    pub fn to_signer(&self) -> Signer {
+        Signer(self.0.verifying_key())
+    }
+}
+
+impl fmt::Display for SigningIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode(SIGNING_KEY_HRP, &self.0.to_bytes()).to_uppercase())
+    }
+}
+
+impl fmt::Debug for SigningIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SigningIdentity({})", self.to_signer()) //never print the secret
+    }
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encode(SIGNER_HRP, self.0.as_bytes()))
+    }
+}
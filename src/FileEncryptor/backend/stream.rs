@@ -0,0 +1,152 @@
+use std::io::{self, Read, Write};
+
+use super::{Cipher, CryptoError};
+
+pub const SEGMENT_LEN: usize = 64 * 1024;
+pub const NONCE_SUFFIX_LEN: usize = 5;
+
+// STREAM nonce: random prefix || 32-bit big-endian segment counter || last segment flag
+fn segment_nonce(prefix: &[u8], counter: u32, last: bool) -> Vec<u8> {
+    let mut nonce = Vec::with_capacity(prefix.len() + NONCE_SUFFIX_LEN);
+    nonce.extend_from_slice(prefix);
+    nonce.extend_from_slice(&counter.to_be_bytes());
+    nonce.push(last as u8);
+    nonce
+}
+
+fn next_counter(counter: u32) -> io::Result<u32> {
+    counter.checked_add(1).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Too many segments in encrypted stream"))
+}
+
+pub struct StreamWriter<W: Write> { //seals everything written to it as fixed-size segments
+    inner: W,
+    cipher: Cipher,
+    prefix: Vec<u8>,
+    aad: Vec<u8>,
+    counter: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(inner: W, cipher: Cipher, prefix: &[u8], aad: &[u8]) -> Self {
+        Self {
+            inner,
+            cipher,
+            prefix: prefix.to_vec(),
+            aad: aad.to_vec(),
+            counter: 0,
+            buffer: Vec::with_capacity(SEGMENT_LEN + 1),
+        }
+    }
+
+    fn seal_segment(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let nonce = segment_nonce(&self.prefix, self.counter, last);
+        let ciphertext = self.cipher.encrypt(&nonce, &self.aad, &self.buffer[..len]).map_err(io::Error::from)?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.drain(..len);
+        self.counter = next_counter(self.counter)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, CryptoError> { //seal whatever is left as the last segment
+        let len = self.buffer.len();
+        self.seal_segment(len, true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // a full segment is only sealed once more data follows it, so the last segment is never empty
+        // unless the whole stream is
+        let take = buf.len().min(SEGMENT_LEN + 1 - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..take]);
+        if self.buffer.len() > SEGMENT_LEN {
+            self.seal_segment(SEGMENT_LEN, false)?;
+        }
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct StreamReader<R: Read> { //opens the segments produced by StreamWriter, checking order and completeness
+    inner: R,
+    cipher: Cipher,
+    prefix: Vec<u8>,
+    aad: Vec<u8>,
+    counter: u32,
+    buffer: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    done: bool,
+}
+
+impl<R: Read> StreamReader<R> {
+    pub fn new(inner: R, cipher: Cipher, prefix: &[u8], aad: &[u8]) -> Self {
+        Self {
+            inner,
+            cipher,
+            prefix: prefix.to_vec(),
+            aad: aad.to_vec(),
+            counter: 0,
+            buffer: Vec::new(),
+            plaintext: Vec::new(),
+            position: 0,
+            done: false,
+        }
+    }
+
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        let wanted = SEGMENT_LEN + self.cipher.tag_len() + 1;
+        while self.buffer.len() < wanted {
+            let start = self.buffer.len();
+            self.buffer.resize(wanted, 0);
+            match self.inner.read(&mut self.buffer[start..]) {
+                Ok(0) => { self.buffer.truncate(start); break; }
+                Ok(n) => self.buffer.truncate(start + n),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => self.buffer.truncate(start),
+                Err(e) => { self.buffer.truncate(start); return Err(e); }
+            }
+        }
+        Ok(())
+    }
+
+    fn open_segment(&mut self) -> io::Result<()> {
+        self.fill_buffer()?;
+        // one byte of lookahead tells whether this is the final segment
+        let tag_len = self.cipher.tag_len();
+        let last = self.buffer.len() <= SEGMENT_LEN + tag_len;
+        let len = self.buffer.len().min(SEGMENT_LEN + tag_len);
+        if len < tag_len || (last && len == tag_len && self.counter > 0) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Encrypted stream is truncated"));
+        }
+
+        let nonce = segment_nonce(&self.prefix, self.counter, last);
+        self.plaintext = self.cipher.decrypt(&nonce, &self.aad, &self.buffer[..len]).map_err(io::Error::from)?;
+        self.position = 0;
+        self.buffer.drain(..len);
+        self.counter = next_counter(self.counter)?;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.open_segment()?;
+        }
+
+        let n = buf.len().min(self.plaintext.len() - self.position);
+        buf[..n].copy_from_slice(&self.plaintext[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
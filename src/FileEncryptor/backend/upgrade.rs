@@ -0,0 +1,136 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::header::{FLAG_ARCHIVE, FLAG_CONTAINER, FLAG_METADATA, FLAG_MIRROR};
+use super::keyslot::KeySlot;
+use super::{change_credentials, decrypt_v0, decrypt_with_key, header_with_slots, key_slots, new_header, open_encrypted, passphrase_kdfs, seal, unlock};
+use super::{Algorithm, Compression, Credential, CryptoError, EncryptOptions, Encrypted, Kdf, Metadata, Padding, EXT_COMPRESSION, EXT_KEY_SLOT, EXT_PADDING, EXT_SIGNATURE};
+
+#[derive(Debug, Default)]
+pub struct UpgradeReport {
+    pub upgraded: usize,
+    pub current: usize, //already on the target algorithm and KDF
+    pub skipped: usize, //not FileEncryptor files, or archives of several files and mirror keys
+    pub failed: Vec<(PathBuf, CryptoError)>, //one file failing doesn't stop the others
+}
+
+fn stale(slot: &KeySlot, kdf: &Kdf) -> bool {
+    matches!(slot, KeySlot::Passphrase { kdf: old, .. } if old != kdf)
+}
+
+fn upgradable(flags: u32) -> bool {
+    flags & (FLAG_CONTAINER | FLAG_MIRROR) == 0
+}
+
+// re-encrypts a file under another algorithm and stretches the given passphrase with another KDF. The plaintext goes
+// straight from the old payload into the new one and the new file only replaces the old one once it is complete.
+// The file key is kept, so the slots of other passphrases, keys and recipients keep working; their KDFs only change
+// when they are upgraded themselves. A signed file is refused rather than re-encrypted, as that would drop its
+// signature; changing only its passphrase's KDF keeps the signature valid. Returns false if there was nothing to do
+pub fn upgrade_file(path: &Path, credential: &Credential, algorithm: Algorithm, kdf: Kdf) -> Result<bool, CryptoError> {
+    let (header, input) = match open_encrypted(File::open(path)?)? {
+        Encrypted::Versioned(header, _) if !upgradable(header.flags) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Archives of several files and mirror keys can't be upgraded").into()),
+        Encrypted::Versioned(header, input) => (header, input),
+        Encrypted::Unversioned(input) => return upgrade_v0(path, input, credential, algorithm, kdf).map(|_| true),
+        Encrypted::Age(..) | Encrypted::OpenSsl(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "age and OpenSSL files can only be decrypted and encrypted again").into()),
+    };
+    let passphrase = matches!(credential, Credential::Passphrase { .. });
+    let key = unlock(&header, credential)?; //a wrong credential is an error, never "already up to date"
+
+    if header.algorithm == algorithm { //the payload can stay, at most a key slot changes
+        if !passphrase || passphrase_kdfs(&header).iter().all(|old| *old == kdf) {
+            return Ok(false);
+        }
+        for slot in key_slots(&header).iter().filter(|slot| stale(slot, &kdf)) {
+            if slot.matches(credential)? {
+                change_credentials(path, credential, std::slice::from_ref(credential), std::slice::from_ref(credential), Some(kdf))?;
+                return Ok(true);
+            }
+        }
+        return Ok(false); //the stale slots are someone else's
+    }
+    if header.extension(EXT_SIGNATURE).is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "This file is signed and re-encrypting it would drop the signature, decrypt it and encrypt and sign it again instead").into());
+    }
+
+    let options = EncryptOptions {
+        compression: header.extension(EXT_COMPRESSION).map(Compression::from_bytes).transpose()?,
+        padding: header.extension(EXT_PADDING).map(Padding::from_bytes).transpose()?,
+        keep_owner: false, //the owner is in the metadata already if it was kept
+    };
+    let flags = (header.flags & FLAG_ARCHIVE) | FLAG_METADATA;
+    let mut slots = Vec::new();
+    for raw in header.extensions_of(EXT_KEY_SLOT) {
+        match KeySlot::parse(raw) {
+            Some(slot) if stale(&slot, &kdf) && slot.matches(credential)? => slots.push(KeySlot::seal(credential, &kdf, &key)?.to_bytes()),
+            _ => slots.push(raw.to_vec()),
+        }
+    }
+    let upgraded = header_with_slots(algorithm, slots, &key, options, flags);
+
+    let empty = Metadata::default(); //files without a metadata block get an empty one
+    decrypt_with_key(&header, &key, input, |reader, metadata| {
+        seal(path, &upgraded, &key, options, metadata.unwrap_or(&empty), |writer| io::copy(reader, writer).map(|_| ())).map_err(io::Error::from)
+    })?;
+    Ok(true)
+}
+
+fn upgrade_v0<R: io::Read>(path: &Path, input: R, credential: &Credential, algorithm: Algorithm, kdf: Kdf) -> Result<(), CryptoError> {
+    let Credential::Passphrase { passphrase, .. } = credential else {
+        return Err(CryptoError::PassphraseRequired);
+    };
+    let credential = Credential::Passphrase { passphrase: passphrase.clone(), keyfile: None }; //these files never had one
+    let (header, key) = new_header(std::slice::from_ref(&credential), algorithm, kdf, EncryptOptions::default(), FLAG_METADATA)?;
+    seal(path, &header, &key, EncryptOptions::default(), &Metadata::default(), |mut writer| decrypt_v0(input, passphrase, &mut writer).map_err(io::Error::from))
+}
+
+// upgrades every FileEncryptor file under dir, files that are already current are only read up to the end of their header
+// and counted as such, so running it again after an interrupted run picks up where that one stopped
+pub fn upgrade_directory(dir: &Path, credential: &Credential, algorithm: Algorithm, kdf: Kdf) -> Result<UpgradeReport, CryptoError> {
+    let mut report = UpgradeReport::default();
+    upgrade_folder(dir, credential, algorithm, &kdf, &mut report)?;
+    Ok(report)
+}
+
+fn ours(path: &Path, file_type: fs::FileType) -> bool { //unversioned files can't be told apart from any other file, so only files with a header are picked up
+    file_type.is_file() && matches!(File::open(path).map_err(CryptoError::from).and_then(open_encrypted), Ok(Encrypted::Versioned(header, _)) if upgradable(header.flags))
+}
+
+// the first file upgrade_directory would pick up, so the UI can ask for what the files in a folder are locked with
+pub fn first_upgradable(dir: &Path) -> Result<Option<PathBuf>, CryptoError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let (path, file_type) = (entry.path(), entry.file_type()?);
+        if file_type.is_dir() {
+            if let Some(found) = first_upgradable(&path)? {
+                return Ok(Some(found));
+            }
+        } else if ours(&path, file_type) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+fn upgrade_folder(dir: &Path, credential: &Credential, algorithm: Algorithm, kdf: &Kdf, report: &mut UpgradeReport) -> Result<(), CryptoError> {
+    let entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?; //listed up front, upgrading adds and renames files
+    for entry in entries {
+        let path = entry.path();
+        let file_type = entry.file_type()?; //symlinks are not followed
+        if file_type.is_dir() {
+            upgrade_folder(&path, credential, algorithm, kdf, report)?;
+            continue;
+        }
+        if !ours(&path, file_type) {
+            report.skipped += 1;
+            continue;
+        }
+        match upgrade_file(&path, credential, algorithm, kdf.clone()) {
+            Ok(true) => report.upgraded += 1,
+            Ok(false) => report.current += 1,
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+    Ok(())
+}